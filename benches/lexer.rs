@@ -0,0 +1,48 @@
+//! Lexes generated sources of growing size, to check that lexing time grows linearly.
+//! Run with `cargo bench --bench lexer`.
+
+use cahlang_ast::{token::TokenType, Lexer};
+use std::time::{Duration, Instant};
+
+const CHUNK: &str = r#"? Sums the numbers below n, with a greeting in a few scripts.
+ritual sum(n) {
+    offering total = 0
+    offering i = 0
+    while i < n {
+        total = total + i * 2.5
+        i = i + 1
+    }
+    $< "héllo wörld, こんにちは, 👋 " + total
+    return total
+}
+sum(10)
+"#;
+
+fn generate(bytes: usize) -> String {
+    CHUNK.repeat(bytes / CHUNK.len() + 1)
+}
+
+/// Lexes the whole source, returning how long it took and how many tokens it made.
+fn lex(source: &str) -> (Duration, usize) {
+    let mut lexer = Lexer::new(source.to_owned());
+    let start = Instant::now();
+    let mut count = 0;
+    while lexer.lex().token_type != TokenType::EOF {
+        count += 1;
+    }
+    (start.elapsed(), count)
+}
+
+fn main() {
+    let mut first: Option<f64> = None;
+    for megabytes in [1, 2, 4, 8, 16] {
+        let source = generate(megabytes << 20);
+        // The fastest of a few runs, to reduce noise.
+        let (time, tokens) = (0..3).map(|_| lex(&source)).min().unwrap();
+        let ns_per_byte = time.as_nanos() as f64 / source.len() as f64;
+        let relative = ns_per_byte / *first.get_or_insert(ns_per_byte);
+        println!(
+            "{megabytes:>3} MB: {tokens:>9} tokens in {time:>10.2?} ({ns_per_byte:.2} ns/byte, {relative:.2}x the 1 MB rate)"
+        );
+    }
+}
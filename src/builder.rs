@@ -0,0 +1,151 @@
+use crate::{
+    cancellation::CancellationToken,
+    convert::IntoNativeFunction,
+    error::{SharedErrorHandler, StdErrorHandler},
+    interpreter::{Backend, Interpreter, InterpreterConfig},
+    module::NativeModule,
+    stdlib,
+    value::{NativeFunction, Value},
+};
+use std::{
+    cell::RefCell,
+    io::{stdin, stdout, BufRead, BufReader, Write},
+    rc::Rc,
+    time::Duration,
+};
+
+/// Configures and creates an `Interpreter`.
+/// Anything not set falls back to stdin, stdout, a `StdErrorHandler` and no budgets.
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    err_handler: Option<SharedErrorHandler>,
+    output: Option<Box<dyn Write>>,
+    input: Option<Box<dyn BufRead>>,
+    modules: Vec<NativeModule>,
+    natives: Vec<NativeFunction>,
+    globals: Vec<(String, Value)>,
+    config: InterpreterConfig,
+    cancellation: Option<CancellationToken>,
+}
+
+impl InterpreterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error_handler(mut self, err_handler: SharedErrorHandler) -> Self {
+        self.err_handler = Some(err_handler);
+        self
+    }
+
+    /// Sets the sink that `$<` and printing natives write to, such as an `OutputBuffer`.
+    pub fn output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Sets the stream that reading natives read from.
+    pub fn input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn module(mut self, module: NativeModule) -> Self {
+        self.modules.push(module);
+        self
+    }
+
+    /// Enables every module in the standard library.
+    pub fn stdlib(self) -> Self {
+        self.module(stdlib::math())
+            .module(stdlib::io())
+            .module(stdlib::string())
+    }
+
+    pub fn native(mut self, func: NativeFunction) -> Self {
+        self.natives.push(func);
+        self
+    }
+
+    /// Adds an ordinary Rust function or closure, converting it like `Interpreter::register_fn`.
+    pub fn function<Args>(self, name: impl ToString, func: impl IntoNativeFunction<Args>) -> Self {
+        self.native(func.into_native_function(name))
+    }
+
+    /// Defines a global variable before any script runs.
+    pub fn global(mut self, name: impl ToString, value: Value) -> Self {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Sets the maximum depth of nested calls, which is `DEFAULT_RECURSION_LIMIT` otherwise.
+    pub fn recursion_limit(mut self, limit: usize) -> Self {
+        self.config.recursion_limit = limit;
+        self
+    }
+
+    /// Sets the maximum number of statements and expressions evaluated per run.
+    pub fn instruction_budget(mut self, budget: u64) -> Self {
+        self.config.instruction_budget = Some(budget);
+        self
+    }
+
+    /// Sets the wall-clock time each run may take.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.config.time_limit = Some(limit);
+        self
+    }
+
+    /// Sets roughly how many bytes each run may allocate for new strings and for values returned
+    /// by natives. See `InterpreterConfig::allocation_limit`.
+    pub fn allocation_limit(mut self, bytes: usize) -> Self {
+        self.config.allocation_limit = Some(bytes);
+        self
+    }
+
+    /// Sets the token that stops running scripts when cancelled.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Makes `+` on a string error for operands that aren't strings, instead of converting them.
+    pub fn strict_concat(mut self, strict: bool) -> Self {
+        self.config.strict_concat = strict;
+        self
+    }
+
+    /// Folds constants and removes dead code before interpreting scripts.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.config.optimize = optimize;
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let err_handler = self
+            .err_handler
+            .unwrap_or_else(|| Rc::new(RefCell::new(StdErrorHandler::new())));
+        let output = self.output.unwrap_or_else(|| Box::new(stdout()));
+        let input = self
+            .input
+            .unwrap_or_else(|| Box::new(BufReader::new(stdin())));
+        let cancellation = self.cancellation.unwrap_or_default();
+        let interpreter =
+            Interpreter::from_parts(err_handler, output, input, self.config, cancellation);
+        for module in self.modules {
+            interpreter.register_module(module);
+        }
+        for func in self.natives {
+            interpreter.register_native(func);
+        }
+        for (name, value) in self.globals {
+            interpreter.define_global(name, value);
+        }
+        interpreter
+    }
+}
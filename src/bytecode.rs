@@ -0,0 +1,78 @@
+use crate::{span::Span, value::Value};
+use std::rc::Rc;
+
+/// A single VM instruction. Operands index into the constant pool, the name table,
+/// the local slots or the code of the prototype the instruction belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes a constant, turning function prototypes into rituals.
+    Constant(u32),
+    Pop,
+    GetLocal(u16),
+    /// Assigns the top of the stack to a local, leaving it on the stack.
+    SetLocal(u16),
+    /// Pops the top of the stack into a local.
+    DefineLocal(u16),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    DefineGlobal(u32),
+    GetProperty(u32),
+    /// Fails if the top of the stack can't have its properties set.
+    CheckSettable(u32),
+    /// Sets a property on the object below the top of the stack, leaving the value.
+    SetProperty(u32),
+    Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Equal,
+    Jump(u32),
+    /// Pops the top of the stack and jumps if it is falsy.
+    JumpIfFalse(u32),
+    /// Jumps if the top of the stack is falsy, leaving it on the stack.
+    JumpIfFalseKeep(u32),
+    /// Jumps if the top of the stack is truthy, leaving it on the stack.
+    JumpIfTrueKeep(u32),
+    /// Jumps backwards to the start of a loop.
+    Loop(u32),
+    /// Calls the value below the given number of arguments.
+    Call(u16),
+    /// Like `Call`, but a ritual being called replaces the current frame instead of
+    /// nesting. Always followed by a `Return` for when it can't.
+    TailCall(u16),
+    Print,
+    Return,
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Value(Value),
+    Function(Rc<Prototype>),
+}
+
+/// Compiled code for a ritual or a top-level statement.
+#[derive(Debug, Clone, Default)]
+pub struct Prototype {
+    pub name: String,
+    pub arity: u16,
+    /// How many local slots the code needs, starting with the parameters.
+    pub local_count: u16,
+    pub code: Vec<Instruction>,
+    /// The source location of each instruction, for error messages.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Constant>,
+    /// Names of globals and properties.
+    pub names: Vec<String>,
+}
+
+/// A compiled script, with one prototype for each top-level statement.
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub statements: Vec<Rc<Prototype>>,
+}
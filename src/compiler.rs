@@ -0,0 +1,324 @@
+use crate::{
+    bytecode::{Constant, Instruction, Program, Prototype},
+    error::{Result, RuntimeError},
+    expression::{CallExpression, Expression, LogicalExpression},
+    span::Span,
+    statement::{FunctionStatement, Statement},
+    token::{Token, TokenType},
+    value::Value,
+};
+use std::rc::Rc;
+
+/// Compiles statements into prototypes for the VM.
+///
+/// Names declared inside blocks and rituals are resolved to local slots while compiling,
+/// everything else is looked up as a global when it runs, matching the tree-walking backend.
+pub struct Compiler {
+    proto: Prototype,
+    /// The names declared in each enclosing block, with their slots.
+    scopes: Vec<Vec<(String, u16)>>,
+    next_slot: u16,
+}
+
+impl Compiler {
+    fn new(name: impl ToString) -> Self {
+        Self {
+            proto: Prototype {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            scopes: vec![],
+            next_slot: 0,
+        }
+    }
+
+    /// Compiles a top-level statement into a prototype that takes no arguments.
+    pub fn compile_statement(statement: &Statement) -> Result<Rc<Prototype>> {
+        let mut compiler = Self::new("<script>");
+        compiler.statement(statement)?;
+        compiler.finish(statement.span())
+    }
+
+    /// Compiles a whole script, so it can be saved and run later.
+    pub fn compile_program(statements: &[Statement]) -> Result<Program> {
+        let statements = statements
+            .iter()
+            .map(Self::compile_statement)
+            .collect::<Result<_>>()?;
+        Ok(Program { statements })
+    }
+
+    fn compile_function(statement: &FunctionStatement) -> Result<Rc<Prototype>> {
+        let mut compiler = Self::new(&statement.name.lexeme);
+        compiler.proto.arity = statement.params.len() as u16;
+        compiler.scopes.push(vec![]);
+        for param in &statement.params {
+            compiler.declare_local(param)?;
+        }
+        for statement in &statement.body {
+            compiler.statement(statement)?;
+        }
+        compiler.finish(statement.span)
+    }
+
+    fn finish(mut self, span: Span) -> Result<Rc<Prototype>> {
+        let none = self.constant(Constant::Value(Value::None), span)?;
+        self.emit(Instruction::Constant(none), span);
+        self.emit(Instruction::Return, span);
+        Ok(Rc::new(self.proto))
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.proto.code.push(instruction);
+        self.proto.spans.push(span);
+        self.proto.code.len() - 1
+    }
+
+    fn index(len: usize, span: Span, what: &str) -> Result<u32> {
+        u32::try_from(len).map_err(|_| RuntimeError::new(span, format!("Too many {what}.")))
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> Result<u32> {
+        let index = Self::index(self.proto.constants.len(), span, "constants")?;
+        self.proto.constants.push(constant);
+        Ok(index)
+    }
+
+    fn name(&mut self, name: &str, span: Span) -> Result<u32> {
+        if let Some(x) = self.proto.names.iter().position(|x| x == name) {
+            return Ok(x as u32);
+        }
+        let index = Self::index(self.proto.names.len(), span, "names")?;
+        self.proto.names.push(name.to_owned());
+        Ok(index)
+    }
+
+    /// Returns the index of the next instruction, for jumps that go back to it.
+    fn here(&self, span: Span) -> Result<u32> {
+        Self::index(self.proto.code.len(), span, "instructions")
+    }
+
+    /// Points a previously emitted forward jump at the next instruction.
+    fn patch_jump(&mut self, jump: usize, span: Span) -> Result<()> {
+        let target = self.here(span)?;
+        self.proto.code[jump] = match self.proto.code[jump] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            Instruction::JumpIfFalseKeep(_) => Instruction::JumpIfFalseKeep(target),
+            Instruction::JumpIfTrueKeep(_) => Instruction::JumpIfTrueKeep(target),
+            x => x,
+        };
+        Ok(())
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u16> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(x, _)| x == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    /// Returns the slot for a name declared in the innermost block, reusing it if it was declared before.
+    fn declare_local(&mut self, name: &Token) -> Result<u16> {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("declared a local outside a block");
+        if let Some((_, slot)) = scope.iter().find(|(x, _)| *x == name.lexeme) {
+            return Ok(*slot);
+        }
+        let slot = self.next_slot;
+        self.next_slot = slot
+            .checked_add(1)
+            .ok_or_else(|| RuntimeError::new(name.span, "Too many local variables."))?;
+        self.proto.local_count = self.proto.local_count.max(self.next_slot);
+        scope.push((name.lexeme.clone(), slot));
+        Ok(slot)
+    }
+
+    /// Stores the value on top of the stack in a new variable in the current scope.
+    fn define(&mut self, name: &Token, span: Span) -> Result<()> {
+        if self.scopes.is_empty() {
+            let index = self.name(&name.lexeme, name.span)?;
+            self.emit(Instruction::DefineGlobal(index), span);
+        } else {
+            let slot = self.declare_local(name)?;
+            self.emit(Instruction::DefineLocal(slot), span);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<()> {
+        let start = self.next_slot;
+        self.scopes.push(vec![]);
+        let result = statements.iter().try_for_each(|x| self.statement(x));
+        self.scopes.pop();
+        self.next_slot = start;
+        result
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::Expression(x) => {
+                self.expression(&x.expr)?;
+                self.emit(Instruction::Pop, x.expr.span());
+            }
+            Statement::Print(x) => {
+                self.expression(&x.expr)?;
+                self.emit(Instruction::Print, x.span);
+            }
+            Statement::Var(x) => {
+                match &x.initializer {
+                    Some(init) => self.expression(init)?,
+                    None => {
+                        let none = self.constant(Constant::Value(Value::None), x.span)?;
+                        self.emit(Instruction::Constant(none), x.span);
+                    }
+                }
+                self.define(&x.name, x.span)?;
+            }
+            Statement::Function(x) => {
+                let proto = Self::compile_function(x)?;
+                let index = self.constant(Constant::Function(proto), x.span)?;
+                self.emit(Instruction::Constant(index), x.span);
+                self.define(&x.name, x.span)?;
+            }
+            Statement::Block(x) => self.block(&x.statements)?,
+            Statement::If(x) => {
+                self.expression(&x.condition)?;
+                let to_else = self.emit(Instruction::JumpIfFalse(0), x.span);
+                self.block(&x.then_branch.statements)?;
+                match &x.else_branch {
+                    Some(else_branch) => {
+                        let to_end = self.emit(Instruction::Jump(0), x.span);
+                        self.patch_jump(to_else, x.span)?;
+                        self.block(&else_branch.statements)?;
+                        self.patch_jump(to_end, x.span)?;
+                    }
+                    None => self.patch_jump(to_else, x.span)?,
+                }
+            }
+            Statement::While(x) => {
+                let start = self.here(x.span)?;
+                self.expression(&x.condition)?;
+                let to_end = self.emit(Instruction::JumpIfFalse(0), x.span);
+                self.block(&x.body.statements)?;
+                self.emit(Instruction::Loop(start), x.span);
+                self.patch_jump(to_end, x.span)?;
+            }
+            Statement::Return(x) => {
+                match &x.expr {
+                    Some(Expression::Call(call)) => self.call(call, true)?,
+                    Some(expr) => self.expression(expr)?,
+                    None => {
+                        let none = self.constant(Constant::Value(Value::None), x.keyword.span)?;
+                        self.emit(Instruction::Constant(none), x.keyword.span);
+                    }
+                }
+                self.emit(Instruction::Return, statement.span());
+            }
+        }
+        Ok(())
+    }
+
+    fn logical(&mut self, expr: &LogicalExpression) -> Result<()> {
+        let span = expr.operator.span;
+        self.expression(&expr.left)?;
+        let jump = if expr.operator.token_type == TokenType::Or {
+            Instruction::JumpIfTrueKeep(0)
+        } else {
+            Instruction::JumpIfFalseKeep(0)
+        };
+        let to_end = self.emit(jump, span);
+        self.emit(Instruction::Pop, span);
+        self.expression(&expr.right)?;
+        self.patch_jump(to_end, span)
+    }
+
+    fn call(&mut self, expr: &CallExpression, tail: bool) -> Result<()> {
+        self.expression(&expr.callee)?;
+        for arg in &expr.args {
+            self.expression(arg)?;
+        }
+        let span = expr.callee.span().to(expr.paren.span);
+        let count = u16::try_from(expr.args.len())
+            .map_err(|_| RuntimeError::new(span, "Too many arguments."))?;
+        let instruction = if tail {
+            Instruction::TailCall(count)
+        } else {
+            Instruction::Call(count)
+        };
+        self.emit(instruction, span);
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<()> {
+        match expr {
+            Expression::Literal(x) => {
+                let index = self.constant(Constant::Value(x.value.clone()), x.span)?;
+                self.emit(Instruction::Constant(index), x.span);
+            }
+            Expression::Grouping(x) => self.expression(&x.expr)?,
+            Expression::Unary(x) => {
+                self.expression(&x.right)?;
+                let instruction = match x.operator.token_type {
+                    TokenType::Not => Instruction::Not,
+                    _ => Instruction::Negate,
+                };
+                self.emit(instruction, x.operator.span);
+            }
+            Expression::Binary(x) => {
+                self.expression(&x.left)?;
+                self.expression(&x.right)?;
+                let instruction = match x.operator.token_type {
+                    TokenType::Plus => Instruction::Add,
+                    TokenType::Minus => Instruction::Subtract,
+                    TokenType::Multiply => Instruction::Multiply,
+                    TokenType::Divide => Instruction::Divide,
+                    TokenType::Greater => Instruction::Greater,
+                    TokenType::GreaterEqual => Instruction::GreaterEqual,
+                    TokenType::Less => Instruction::Less,
+                    TokenType::LessEqual => Instruction::LessEqual,
+                    TokenType::Is => Instruction::Equal,
+                    _ => {
+                        let msg = "Unknown operator in binary expression.";
+                        return Err(RuntimeError::new(x.operator.span, msg));
+                    }
+                };
+                self.emit(instruction, x.operator.span);
+            }
+            Expression::Variable(x) => {
+                let instruction = match self.resolve_local(&x.name.lexeme) {
+                    Some(slot) => Instruction::GetLocal(slot),
+                    None => Instruction::GetGlobal(self.name(&x.name.lexeme, x.name.span)?),
+                };
+                self.emit(instruction, x.name.span);
+            }
+            Expression::Assign(x) => {
+                self.expression(&x.value)?;
+                let instruction = match self.resolve_local(&x.name.lexeme) {
+                    Some(slot) => Instruction::SetLocal(slot),
+                    None => Instruction::SetGlobal(self.name(&x.name.lexeme, x.name.span)?),
+                };
+                self.emit(instruction, x.name.span);
+            }
+            Expression::Logical(x) => self.logical(x)?,
+            Expression::Call(x) => self.call(x, false)?,
+            Expression::Get(x) => {
+                self.expression(&x.object)?;
+                let index = self.name(&x.name.lexeme, x.name.span)?;
+                self.emit(Instruction::GetProperty(index), x.name.span);
+            }
+            Expression::Set(x) => {
+                self.expression(&x.object)?;
+                let index = self.name(&x.name.lexeme, x.name.span)?;
+                self.emit(Instruction::CheckSettable(index), x.name.span);
+                self.expression(&x.value)?;
+                self.emit(Instruction::SetProperty(index), x.name.span);
+            }
+        }
+        Ok(())
+    }
+}
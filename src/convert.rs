@@ -0,0 +1,293 @@
+use crate::{
+    error::{Result, RuntimeError},
+    interpreter::Interpreter,
+    value::{NativeFunction, Value},
+};
+use std::{collections::HashMap, fmt::Display};
+
+/// Conversion from a script value into a Rust value.
+pub trait FromValue: Sized {
+    /// Converts the value, returning a description of the expected type if it doesn't match.
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError>;
+}
+
+/// Conversion from a Rust value into a script value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionError {
+    pub expected: String,
+    pub found: String,
+}
+
+impl ConversionError {
+    pub fn new(expected: impl ToString, found: &Value) -> Self {
+        Self {
+            expected: expected.to_string(),
+            found: found.type_name().to_owned(),
+        }
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::None
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::Number(x) => Ok(x),
+            x => Err(ConversionError::new("number", &x)),
+        }
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+macro_rules! impl_integer_conversion {
+    ($($int:ty),*) => {
+        $(
+            impl FromValue for $int {
+                fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+                    const EXPECTED: &str = concat!("integer (", stringify!($int), ")");
+                    // `MAX as f64` rounds up to `MAX + 1` for 64-bit integers, so the upper bound
+                    // is checked against `MAX + 1` instead, a power of two that f64 holds exactly.
+                    let end = <$int>::MAX as f64 + 1.0;
+                    match value {
+                        Value::Number(x)
+                            if x.fract() == 0.0 && x >= <$int>::MIN as f64 && x < end =>
+                        {
+                            Ok(x as $int)
+                        }
+                        x => Err(ConversionError::new(EXPECTED, &x)),
+                    }
+                }
+            }
+
+            impl IntoValue for $int {
+                fn into_value(self) -> Value {
+                    Value::Number(self as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_conversion!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::Boolean(x) => Ok(x),
+            x => Err(ConversionError::new("boolean", &x)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::String(x) => Ok(x),
+            x => Err(ConversionError::new("string", &x)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_owned())
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::None => Ok(None),
+            x => T::from_value(x).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(x) => x.into_value(),
+            None => Value::None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::List(x) => x.into_iter().map(T::from_value).collect(),
+            x => Err(ConversionError::new("list", &x)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::Map(x) => x
+                .into_iter()
+                .map(|(key, val)| Ok((key, T::from_value(val)?)))
+                .collect(),
+            x => Err(ConversionError::new("map", &x)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        Value::Map(
+            self.into_iter()
+                .map(|(key, val)| (key, val.into_value()))
+                .collect(),
+        )
+    }
+}
+
+/// Conversion from a Rust function or closure into a native function.
+/// `Args` is the tuple of argument types, and is inferred from the function signature.
+pub trait IntoNativeFunction<Args> {
+    fn into_native_function(self, name: impl ToString) -> NativeFunction;
+}
+
+/// A return type of functions turned into native functions. Every `IntoValue` type is one, and
+/// so is `Result`, which lets them fail. The error is raised at the call, with its `Display` text
+/// as the message.
+pub trait IntoNativeResult {
+    fn into_native_result(self, interpreter: &Interpreter) -> Result<Value>;
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+    fn into_native_result(self, _interpreter: &Interpreter) -> Result<Value> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: Display> IntoNativeResult for std::result::Result<T, E> {
+    fn into_native_result(self, interpreter: &Interpreter) -> Result<Value> {
+        match self {
+            Ok(x) => Ok(x.into_value()),
+            Err(err) => Err(interpreter.call_error(err)),
+        }
+    }
+}
+
+/// An argument type of functions turned into native functions. Every `FromValue` type is one,
+/// and so is `&str`, which borrows the string passed in instead of taking it.
+pub trait FromArg {
+    /// The argument as the function receives it, which may borrow from the value.
+    type Arg<'a>;
+    fn from_arg(value: &mut Value) -> std::result::Result<Self::Arg<'_>, ConversionError>;
+}
+
+impl<T: FromValue> FromArg for T {
+    type Arg<'a> = T;
+
+    fn from_arg(value: &mut Value) -> std::result::Result<T, ConversionError> {
+        T::from_value(std::mem::replace(value, Value::None))
+    }
+}
+
+impl FromArg for &str {
+    type Arg<'a> = &'a str;
+
+    fn from_arg(value: &mut Value) -> std::result::Result<&str, ConversionError> {
+        match value {
+            Value::String(x) => Ok(x),
+            x => Err(ConversionError::new("string", x)),
+        }
+    }
+}
+
+fn convert_arg<'a, T: FromArg>(
+    interpreter: &Interpreter,
+    func_name: &str,
+    args: &mut impl Iterator<Item = (usize, &'a mut Value)>,
+) -> Result<T::Arg<'a>> {
+    let (index, value) = args
+        .next()
+        .ok_or_else(|| interpreter.call_error(format!("Too few arguments to '{func_name}'")))?;
+    T::from_arg(value).map_err(|err| -> RuntimeError {
+        interpreter.call_error(format!(
+            "Expected argument {} to '{func_name}' to be {}, but got {}",
+            index + 1,
+            err.expected,
+            err.found
+        ))
+    })
+}
+
+macro_rules! impl_into_native_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg,)*> IntoNativeFunction<($($arg,)*)> for F
+        where
+            // The first bound names the argument types, the second lets them borrow from the
+            // arguments for the length of the call.
+            F: Fn($($arg),*) -> R + for<'a> Fn($($arg::Arg<'a>),*) -> R + 'static,
+            R: IntoNativeResult,
+            $($arg: FromArg,)*
+        {
+            fn into_native_function(self, name: impl ToString) -> NativeFunction {
+                let name = name.to_string();
+                let arity = <[&str]>::len(&[$(stringify!($arg)),*]);
+                NativeFunction::new(name.clone(), arity, move |interpreter, mut args| {
+                    let mut args = args.iter_mut().enumerate();
+                    let result = self($(convert_arg::<$arg>(interpreter, &name, &mut args)?),*);
+                    // Calls are checked against the arity before they get here.
+                    debug_assert!(args.next().is_none());
+                    result.into_native_result(interpreter)
+                })
+            }
+        }
+    };
+}
+
+impl_into_native_function!();
+impl_into_native_function!(A);
+impl_into_native_function!(A, B);
+impl_into_native_function!(A, B, C);
+impl_into_native_function!(A, B, C, D);
+impl_into_native_function!(A, B, C, D, E);
+impl_into_native_function!(A, B, C, D, E, G);
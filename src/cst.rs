@@ -0,0 +1,551 @@
+//! A lossless concrete syntax tree, for tools like formatters that need every byte of the source.
+//!
+//! The tree keeps every token, including whitespace, newlines and comments, so printing it gives
+//! back the exact source it was parsed from, even when the source has errors. Typed views such as
+//! [`ScriptNode`] and [`IfNode`] sit on top of the untyped [`SyntaxNode`]s and give access to the
+//! parts of each construct by name.
+
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::Lexer,
+    parser::Parser,
+    span::Span,
+    token::{Token, TokenType},
+};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Script,
+    /// Code that failed to parse, along with the tokens skipped to recover from it.
+    Error,
+
+    // Statements
+    Var,
+    Function,
+    ParamList,
+    Block,
+    If,
+    While,
+    Return,
+    Print,
+    ExpressionStatement,
+
+    // Expressions
+    Literal,
+    Variable,
+    Grouping,
+    Unary,
+    Binary,
+    Logical,
+    /// Assignments with `=`, `+=` and `-=`, to a variable or a property.
+    Assign,
+    /// `x++` and `x--`.
+    Postfix,
+    Call,
+    ArgList,
+    Get,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(Token),
+}
+
+impl SyntaxElement {
+    fn write_text(&self, out: &mut String) {
+        match self {
+            Self::Node(x) => x.write_text(out),
+            Self::Token(x) => out.push_str(&x.lexeme),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    pub fn new(kind: SyntaxKind, children: Vec<SyntaxElement>) -> Self {
+        Self { kind, children }
+    }
+
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|x| match x {
+            SyntaxElement::Node(x) => Some(x),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    pub fn child_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.children.iter().filter_map(|x| match x {
+            SyntaxElement::Token(x) => Some(x),
+            SyntaxElement::Node(_) => None,
+        })
+    }
+
+    /// Returns the first direct child token of the given type.
+    pub fn child_token(&self, token_type: TokenType) -> Option<&Token> {
+        self.child_tokens().find(|x| x.token_type == token_type)
+    }
+
+    /// Returns every token in the node, in source order.
+    pub fn tokens(&self) -> Vec<&Token> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a Token>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(x) => x.collect_tokens(tokens),
+                SyntaxElement::Token(x) => tokens.push(x),
+            }
+        }
+    }
+
+    /// Returns the span from the first to the last token that isn't trivia, if there are any.
+    pub fn span(&self) -> Option<Span> {
+        let tokens = self.tokens();
+        let mut significant = tokens.iter().filter(|x| !x.token_type.is_trivia());
+        let first = significant.next()?;
+        let last = significant.next_back().unwrap_or(first);
+        Some(first.span.to(last.span))
+    }
+
+    /// Returns the source text the node was parsed from, including trivia.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        self.write_text(&mut out);
+        out
+    }
+
+    fn write_text(&self, out: &mut String) {
+        for child in &self.children {
+            child.write_text(out);
+        }
+    }
+}
+
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+/// Collects tokens while the parser runs, and groups them into nodes as constructs finish.
+///
+/// Nodes are built bottom up: the parser takes a checkpoint where a construct starts, and once the
+/// construct has parsed, everything after the checkpoint is wrapped into a node. A construct that
+/// fails to parse is never wrapped, so its tokens stay where they are until the enclosing
+/// declaration wraps them into an [`SyntaxKind::Error`] node.
+#[derive(Default)]
+pub(crate) struct TreeBuilder {
+    elements: Vec<SyntaxElement>,
+}
+
+impl TreeBuilder {
+    pub(crate) fn token(&mut self, token: Token) {
+        self.elements.push(SyntaxElement::Token(token));
+    }
+
+    pub(crate) fn checkpoint(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Wraps everything since `checkpoint` into a node. Trailing trivia is left outside of it, so
+    /// nodes start and end with a significant token.
+    pub(crate) fn wrap(&mut self, checkpoint: usize, kind: SyntaxKind) {
+        let is_trivia =
+            |x: &SyntaxElement| matches!(x, SyntaxElement::Token(x) if x.token_type.is_trivia());
+        let trailing = self.elements[checkpoint..]
+            .iter()
+            .rev()
+            .take_while(|x| is_trivia(x))
+            .count();
+        let trivia = self.elements.split_off(self.elements.len() - trailing);
+        let children = self.elements.split_off(checkpoint);
+        self.elements
+            .push(SyntaxElement::Node(SyntaxNode::new(kind, children)));
+        self.elements.extend(trivia);
+    }
+
+    pub(crate) fn finish(self) -> SyntaxNode {
+        SyntaxNode::new(SyntaxKind::Script, self.elements)
+    }
+}
+
+/// A parsed script that can be turned back into its source byte for byte.
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    root: SyntaxNode,
+    errors: Vec<Diagnostic>,
+}
+
+impl SyntaxTree {
+    /// Parses a script, keeping going after errors so that the tree covers all of the source.
+    pub fn parse(source: &str) -> Self {
+        let mut parser = Parser::with_syntax_tree(Lexer::with_trivia(source.to_owned()));
+        let errors = parser.parse().err().unwrap_or_default();
+        let root = parser.finish_syntax_tree();
+        Self { root, errors }
+    }
+
+    pub fn root(&self) -> &SyntaxNode {
+        &self.root
+    }
+
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
+    pub fn script(&self) -> ScriptNode<'_> {
+        ScriptNode(&self.root)
+    }
+}
+
+impl Display for SyntaxTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.root.fmt(f)
+    }
+}
+
+macro_rules! typed_nodes {
+    ($($(#[$meta:meta])* $name:ident => $kind:ident,)*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy)]
+            pub struct $name<'a>(&'a SyntaxNode);
+
+            impl<'a> $name<'a> {
+                /// Views the node as this type, if it is of the right kind.
+                pub fn cast(node: &'a SyntaxNode) -> Option<Self> {
+                    (node.kind == SyntaxKind::$kind).then_some(Self(node))
+                }
+
+                pub fn syntax(&self) -> &'a SyntaxNode {
+                    self.0
+                }
+            }
+        )*
+    };
+}
+
+typed_nodes! {
+    ScriptNode => Script,
+    VarNode => Var,
+    FunctionNode => Function,
+    BlockNode => Block,
+    IfNode => If,
+    WhileNode => While,
+    ReturnNode => Return,
+    PrintNode => Print,
+    ExpressionStatementNode => ExpressionStatement,
+    LiteralNode => Literal,
+    VariableNode => Variable,
+    GroupingNode => Grouping,
+    UnaryNode => Unary,
+    /// Arithmetic and comparison operators.
+    BinaryNode => Binary,
+    /// `and` and `or`.
+    LogicalNode => Logical,
+    AssignNode => Assign,
+    PostfixNode => Postfix,
+    CallNode => Call,
+    GetNode => Get,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StatementNode<'a> {
+    Var(VarNode<'a>),
+    Function(FunctionNode<'a>),
+    Block(BlockNode<'a>),
+    If(IfNode<'a>),
+    While(WhileNode<'a>),
+    Return(ReturnNode<'a>),
+    Print(PrintNode<'a>),
+    Expression(ExpressionStatementNode<'a>),
+}
+
+impl<'a> StatementNode<'a> {
+    pub fn cast(node: &'a SyntaxNode) -> Option<Self> {
+        let statement = match node.kind {
+            SyntaxKind::Var => Self::Var(VarNode(node)),
+            SyntaxKind::Function => Self::Function(FunctionNode(node)),
+            SyntaxKind::Block => Self::Block(BlockNode(node)),
+            SyntaxKind::If => Self::If(IfNode(node)),
+            SyntaxKind::While => Self::While(WhileNode(node)),
+            SyntaxKind::Return => Self::Return(ReturnNode(node)),
+            SyntaxKind::Print => Self::Print(PrintNode(node)),
+            SyntaxKind::ExpressionStatement => Self::Expression(ExpressionStatementNode(node)),
+            _ => return None,
+        };
+        Some(statement)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExpressionNode<'a> {
+    Literal(LiteralNode<'a>),
+    Variable(VariableNode<'a>),
+    Grouping(GroupingNode<'a>),
+    Unary(UnaryNode<'a>),
+    Binary(BinaryNode<'a>),
+    Logical(LogicalNode<'a>),
+    Assign(AssignNode<'a>),
+    Postfix(PostfixNode<'a>),
+    Call(CallNode<'a>),
+    Get(GetNode<'a>),
+}
+
+impl<'a> ExpressionNode<'a> {
+    pub fn cast(node: &'a SyntaxNode) -> Option<Self> {
+        let expr = match node.kind {
+            SyntaxKind::Literal => Self::Literal(LiteralNode(node)),
+            SyntaxKind::Variable => Self::Variable(VariableNode(node)),
+            SyntaxKind::Grouping => Self::Grouping(GroupingNode(node)),
+            SyntaxKind::Unary => Self::Unary(UnaryNode(node)),
+            SyntaxKind::Binary => Self::Binary(BinaryNode(node)),
+            SyntaxKind::Logical => Self::Logical(LogicalNode(node)),
+            SyntaxKind::Assign => Self::Assign(AssignNode(node)),
+            SyntaxKind::Postfix => Self::Postfix(PostfixNode(node)),
+            SyntaxKind::Call => Self::Call(CallNode(node)),
+            SyntaxKind::Get => Self::Get(GetNode(node)),
+            _ => return None,
+        };
+        Some(expr)
+    }
+
+    pub fn syntax(&self) -> &'a SyntaxNode {
+        match self {
+            Self::Literal(x) => x.0,
+            Self::Variable(x) => x.0,
+            Self::Grouping(x) => x.0,
+            Self::Unary(x) => x.0,
+            Self::Binary(x) => x.0,
+            Self::Logical(x) => x.0,
+            Self::Assign(x) => x.0,
+            Self::Postfix(x) => x.0,
+            Self::Call(x) => x.0,
+            Self::Get(x) => x.0,
+        }
+    }
+}
+
+fn statements(node: &SyntaxNode) -> impl Iterator<Item = StatementNode<'_>> {
+    node.child_nodes().filter_map(StatementNode::cast)
+}
+
+fn expressions(node: &SyntaxNode) -> impl Iterator<Item = ExpressionNode<'_>> {
+    node.child_nodes().filter_map(ExpressionNode::cast)
+}
+
+/// Returns the first direct child token that isn't trivia.
+fn first_token(node: &SyntaxNode) -> Option<&Token> {
+    node.child_tokens().find(|x| !x.token_type.is_trivia())
+}
+
+fn block(node: &SyntaxNode, index: usize) -> Option<BlockNode<'_>> {
+    node.child_nodes().filter_map(BlockNode::cast).nth(index)
+}
+
+impl<'a> ScriptNode<'a> {
+    /// Returns the statements that parsed, skipping those that failed.
+    pub fn statements(&self) -> impl Iterator<Item = StatementNode<'a>> {
+        statements(self.0)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &'a SyntaxNode> {
+        self.0.child_nodes().filter(|x| x.kind == SyntaxKind::Error)
+    }
+}
+
+impl<'a> VarNode<'a> {
+    pub fn name(&self) -> Option<&'a Token> {
+        self.0.child_token(TokenType::Identifier)
+    }
+
+    pub fn initializer(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> FunctionNode<'a> {
+    pub fn name(&self) -> Option<&'a Token> {
+        self.0.child_token(TokenType::Identifier)
+    }
+
+    pub fn params(&self) -> Vec<&'a Token> {
+        self.0
+            .child_nodes()
+            .find(|x| x.kind == SyntaxKind::ParamList)
+            .map(|x| {
+                x.child_tokens()
+                    .filter(|x| x.token_type == TokenType::Identifier)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn body(&self) -> Option<BlockNode<'a>> {
+        block(self.0, 0)
+    }
+}
+
+impl<'a> BlockNode<'a> {
+    pub fn statements(&self) -> impl Iterator<Item = StatementNode<'a>> {
+        statements(self.0)
+    }
+}
+
+impl<'a> IfNode<'a> {
+    pub fn condition(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn then_branch(&self) -> Option<BlockNode<'a>> {
+        block(self.0, 0)
+    }
+
+    pub fn else_branch(&self) -> Option<BlockNode<'a>> {
+        block(self.0, 1)
+    }
+}
+
+impl<'a> WhileNode<'a> {
+    pub fn condition(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn body(&self) -> Option<BlockNode<'a>> {
+        block(self.0, 0)
+    }
+}
+
+impl<'a> ReturnNode<'a> {
+    pub fn value(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> PrintNode<'a> {
+    pub fn value(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> ExpressionStatementNode<'a> {
+    pub fn expression(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> LiteralNode<'a> {
+    pub fn token(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+}
+
+impl<'a> VariableNode<'a> {
+    pub fn name(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+}
+
+impl<'a> GroupingNode<'a> {
+    pub fn expression(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> UnaryNode<'a> {
+    pub fn operator(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+
+    pub fn operand(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+}
+
+impl<'a> BinaryNode<'a> {
+    pub fn left(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn operator(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+
+    pub fn right(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).nth(1)
+    }
+}
+
+impl<'a> LogicalNode<'a> {
+    pub fn left(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn operator(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+
+    pub fn right(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).nth(1)
+    }
+}
+
+impl<'a> AssignNode<'a> {
+    /// The variable or property assigned to.
+    pub fn target(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    /// `=`, `+=` or `-=`.
+    pub fn operator(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+
+    pub fn value(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).nth(1)
+    }
+}
+
+impl<'a> PostfixNode<'a> {
+    pub fn operand(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn operator(&self) -> Option<&'a Token> {
+        first_token(self.0)
+    }
+}
+
+impl<'a> CallNode<'a> {
+    pub fn callee(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn args(&self) -> Vec<ExpressionNode<'a>> {
+        self.0
+            .child_nodes()
+            .find(|x| x.kind == SyntaxKind::ArgList)
+            .map(|x| expressions(x).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<'a> GetNode<'a> {
+    pub fn object(&self) -> Option<ExpressionNode<'a>> {
+        expressions(self.0).next()
+    }
+
+    pub fn name(&self) -> Option<&'a Token> {
+        self.0.child_token(TokenType::Identifier)
+    }
+}
@@ -0,0 +1,195 @@
+use crate::span::Span;
+use std::{
+    fmt::Write,
+    io::{stderr, IsTerminal},
+};
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+/// A message about a region of source, with optional secondary labels, notes and help text.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub msg: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, msg: impl ToString) -> Self {
+        Self {
+            msg: msg.to_string(),
+            span,
+            labels: vec![],
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, msg: impl ToString) -> Self {
+        self.labels.push(Label {
+            span,
+            msg: msg.to_string(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl ToString) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl ToString) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+}
+
+/// A named piece of source text that diagnostics can point into.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl ToString, text: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Returns the byte range of the line containing `offset`, excluding the newline.
+    fn line_bounds(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.text.len() || !self.text.is_char_boundary(offset) {
+            return None;
+        }
+        let start = self.text[..offset].rfind('\n').map_or(0, |x| x + 1);
+        let end = self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |x| offset + x);
+        Some((start, end))
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics into human readable text, with or without ANSI colors.
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub const fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Creates a renderer that uses colors if stderr is a terminal and NO_COLOR is not set.
+    pub fn auto() -> Self {
+        let color = stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        Self::new(color)
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn render_label(
+        &self,
+        out: &mut String,
+        source: &Source,
+        label: &Label,
+        (marker, style): (char, &str),
+        gutter: usize,
+    ) -> Option<()> {
+        let Label { span, msg } = label;
+        let span = *span;
+        let (line_start, line_end) = source.line_bounds(span.start)?;
+        let line_text = &source.text[line_start..line_end];
+        let mut underline_end = span.end.clamp(span.start, line_end);
+        while !source.text.is_char_boundary(underline_end) {
+            underline_end -= 1;
+        }
+        let offset = source.text[line_start..span.start].chars().count();
+        let width = source.text[span.start..underline_end]
+            .chars()
+            .count()
+            .max(1);
+
+        let bar = self.paint(BLUE, "|");
+        let line_no = self.paint(BLUE, &format!("{:>gutter$}", span.line));
+        writeln!(out, "{line_no} {bar} {line_text}").ok();
+        let underline = marker.to_string().repeat(width);
+        let mut annotation = format!("{}{}", " ".repeat(offset), self.paint(style, &underline));
+        if !msg.is_empty() {
+            annotation.push(' ');
+            annotation.push_str(&self.paint(style, msg));
+        }
+        writeln!(out, "{:gutter$} {bar} {annotation}", "").ok();
+        Some(())
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic, source: Option<&Source>) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(RED, "error"),
+            self.paint(BOLD, &format!(": {}", diagnostic.msg))
+        )
+        .ok();
+
+        let name = source.map_or("<unknown>", |x| x.name.as_str());
+        let span = diagnostic.span;
+        let gutter = diagnostic
+            .labels
+            .iter()
+            .map(|x| x.span.line)
+            .fold(span.line, usize::max)
+            .to_string()
+            .len();
+        writeln!(
+            out,
+            "{:gutter$}{} {name}:{}:{}",
+            "",
+            self.paint(BLUE, "-->"),
+            span.line,
+            span.column
+        )
+        .ok();
+
+        if let Some(source) = source {
+            let bar = self.paint(BLUE, "|");
+            writeln!(out, "{:gutter$} {bar}", "").ok();
+            let primary = Label {
+                span,
+                msg: String::new(),
+            };
+            self.render_label(&mut out, source, &primary, ('^', RED), gutter);
+            for label in &diagnostic.labels {
+                self.render_label(&mut out, source, label, ('-', BLUE), gutter);
+            }
+        }
+
+        for note in &diagnostic.notes {
+            writeln!(out, "{:gutter$} {} note: {note}", "", self.paint(BLUE, "=")).ok();
+        }
+        if let Some(help) = &diagnostic.help {
+            writeln!(out, "{:gutter$} {} help: {help}", "", self.paint(BLUE, "=")).ok();
+        }
+        out
+    }
+}
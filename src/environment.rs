@@ -13,23 +13,23 @@ pub struct Environment {
     values: HashMap<String, Value>,
 }
 
-impl Into<Env> for Environment {
-    fn into(self) -> Env {
-        Rc::new(RefCell::new(self))
+impl From<Environment> for Env {
+    fn from(env: Environment) -> Self {
+        Rc::new(RefCell::new(env))
     }
 }
 
-impl<'a> Environment {
+impl Environment {
     pub fn new(enclosing: Option<Env>) -> Self {
         Self {
-            enclosing: enclosing,
+            enclosing,
             values: HashMap::new(),
         }
     }
 
     fn undef_var_err<T>(name: &Token) -> Result<T> {
         let msg = format!("Undefined variable '{}'", &name.lexeme);
-        Err(RuntimeError::new(name.clone(), msg))
+        Err(RuntimeError::new(name.span, msg))
     }
 
     pub fn define(&mut self, name: String, value: Value) {
@@ -51,17 +51,24 @@ impl<'a> Environment {
         Ok(())
     }
 
+    /// Looks up a variable by name, returning None if it isn't defined.
+    pub fn get_by_name(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(x) => Some(x.clone()),
+            None => self.enclosing.as_ref()?.borrow().get_by_name(name),
+        }
+    }
+
     pub fn get(&self, name: &Token) -> Result<Value> {
         match self.values.get(&name.lexeme) {
             Some(x) => Ok(x.clone()),
             None => {
                 if let Some(x) = &self.enclosing {
-                    match x.borrow().get(&name) {
-                        Ok(x) => return Ok(x.clone()),
-                        Err(_) => (),
+                    if let Ok(x) = x.borrow().get(name) {
+                        return Ok(x);
                     }
                 }
-                Self::undef_var_err(&name)
+                Self::undef_var_err(name)
             }
         }
     }
@@ -1,27 +1,93 @@
-use crate::token::Token;
-use once_cell::sync::Lazy;
+use crate::{
+    diagnostic::{Diagnostic, Renderer, Source},
+    span::Span,
+};
 use std::{
+    cell::RefCell,
     error::Error,
     fmt::Display,
     io::{stderr, Write},
-    sync::{Mutex, MutexGuard},
+    rc::Rc,
 };
 
 pub type Result<T> = std::result::Result<T, RuntimeError>;
 
+/// What caused a `RuntimeError`, so hosts can tell script errors apart from exhausted limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An ordinary error raised by the script or a native function.
+    Script,
+    InstructionLimit,
+    TimeLimit,
+    AllocationLimit,
+    /// Calls were nested deeper than the recursion limit.
+    StackOverflow,
+    /// The run was stopped through a `CancellationToken`.
+    Cancelled,
+}
+
+impl ErrorKind {
+    /// Returns true for errors that abort the whole run instead of only the current statement.
+    pub fn is_fatal(self) -> bool {
+        !matches!(self, Self::Script | Self::StackOverflow)
+    }
+}
+
+/// A call that was active when an error happened.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub name: String,
+    pub call_span: Span,
+}
+
+/// How many frames of a traceback are shown in diagnostics.
+const MAX_SHOWN_FRAMES: usize = 10;
+
 #[derive(Debug)]
 pub struct RuntimeError {
-    token: Token,
+    span: Span,
     msg: String,
+    kind: ErrorKind,
+    traceback: Vec<TraceFrame>,
 }
 
 impl RuntimeError {
-    pub fn new(token: Token, msg: impl ToString) -> Self {
+    pub fn new(span: Span, msg: impl ToString) -> Self {
+        Self::with_kind(ErrorKind::Script, span, msg)
+    }
+
+    pub fn with_kind(kind: ErrorKind, span: Span, msg: impl ToString) -> Self {
         Self {
-            token,
+            span,
             msg: msg.to_string(),
+            kind,
+            traceback: vec![],
         }
     }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+
+    pub fn get_kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.kind.is_fatal()
+    }
+
+    /// Returns the calls the error passed through, innermost first.
+    pub fn get_traceback(&self) -> &[TraceFrame] {
+        &self.traceback
+    }
+
+    pub(crate) fn push_frame(&mut self, name: impl ToString, call_span: Span) {
+        self.traceback.push(TraceFrame {
+            name: name.to_string(),
+            call_span,
+        });
+    }
 }
 
 impl Display for RuntimeError {
@@ -32,36 +98,76 @@ impl Display for RuntimeError {
 
 impl Error for RuntimeError {}
 
-impl<S: ToString> From<(Token, S)> for RuntimeError {
-    fn from(x: (Token, S)) -> Self {
-        Self {
-            token: x.0,
-            msg: x.1.to_string(),
-        }
+impl<S: ToString> From<(Span, S)> for RuntimeError {
+    fn from(x: (Span, S)) -> Self {
+        Self::new(x.0, x.1)
     }
 }
 
-static ERR_HANDLER: Lazy<Mutex<Box<(dyn ErrorHandler + Sync + Send)>>> = Lazy::new(|| {
-    Mutex::new(Box::new(StdErrorHandler {
-        had_error: false,
-        had_runtime_error: false,
-    }))
-});
-
-pub fn get_err_handler<'a>() -> MutexGuard<'a, Box<(dyn ErrorHandler + Send + Sync)>> {
-    ERR_HANDLER.lock().unwrap()
+impl From<RuntimeError> for Diagnostic {
+    fn from(err: RuntimeError) -> Self {
+        let mut diagnostic = Diagnostic::error(err.span, err.msg);
+        // Recursion repeats the same frame many times, so runs of equal frames are shown once.
+        let mut frames = err
+            .traceback
+            .chunk_by(|a, b| a.name == b.name && a.call_span == b.call_span);
+        for run in frames.by_ref().take(MAX_SHOWN_FRAMES) {
+            let frame = &run[0];
+            let mut note = format!("in '{}' called at {}", frame.name, frame.call_span);
+            if run.len() > 1 {
+                note += &format!(" ({} times)", run.len());
+            }
+            diagnostic = diagnostic.with_note(note);
+        }
+        let hidden: usize = frames.map(<[TraceFrame]>::len).sum();
+        if hidden > 0 {
+            diagnostic = diagnostic.with_note(format!("... and {hidden} more calls"));
+        }
+        diagnostic
+    }
 }
 
+pub type SharedErrorHandler = Rc<RefCell<dyn ErrorHandler>>;
+
 pub trait ErrorHandler {
     fn had_error(&self) -> bool;
-    fn report(&self, line: usize, msg: &str);
-    fn error(&mut self, token: Token, msg: &str);
+    fn had_runtime_error(&self) -> bool;
+    /// Clears the error state, for example between lines in a REPL.
+    fn reset(&mut self);
+    /// Sets the source that following diagnostics point into.
+    fn set_source(&mut self, _source: Source) {}
+    fn error(&mut self, diagnostic: Diagnostic);
     fn runtime_error(&mut self, err: RuntimeError);
 }
 
+/// Renders every error to stderr.
 pub struct StdErrorHandler {
     had_error: bool,
     had_runtime_error: bool,
+    source: Option<Source>,
+    renderer: Renderer,
+}
+
+impl StdErrorHandler {
+    pub fn new() -> Self {
+        Self {
+            had_error: false,
+            had_runtime_error: false,
+            source: None,
+            renderer: Renderer::auto(),
+        }
+    }
+
+    fn report(&self, diagnostic: &Diagnostic) {
+        let text = self.renderer.render(diagnostic, self.source.as_ref());
+        stderr().write_all(text.as_bytes()).ok();
+    }
+}
+
+impl Default for StdErrorHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ErrorHandler for StdErrorHandler {
@@ -69,19 +175,70 @@ impl ErrorHandler for StdErrorHandler {
         self.had_error
     }
 
-    fn report(&self, line: usize, msg: &str) {
-        stderr()
-            .write_fmt(format_args!("{msg} at line {line}\n"))
-            .ok();
+    fn had_runtime_error(&self) -> bool {
+        self.had_runtime_error
+    }
+
+    fn reset(&mut self) {
+        self.had_error = false;
+        self.had_runtime_error = false;
     }
 
-    fn error(&mut self, token: Token, msg: &str) {
-        self.report(token.line, msg);
+    fn set_source(&mut self, source: Source) {
+        self.source = Some(source);
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.report(&diagnostic);
         self.had_error = true;
     }
 
     fn runtime_error(&mut self, err: RuntimeError) {
-        self.report(err.token.line, &err.msg);
+        self.report(&err.into());
         self.had_runtime_error = true;
     }
 }
+
+/// Stores every error instead of printing it, so they can be inspected afterwards.
+#[derive(Default)]
+pub struct CollectingErrorHandler {
+    errors: Vec<Diagnostic>,
+    runtime_errors: Vec<RuntimeError>,
+}
+
+impl CollectingErrorHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
+    pub fn runtime_errors(&self) -> &[RuntimeError] {
+        &self.runtime_errors
+    }
+}
+
+impl ErrorHandler for CollectingErrorHandler {
+    fn had_error(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    fn had_runtime_error(&self) -> bool {
+        !self.runtime_errors.is_empty()
+    }
+
+    fn reset(&mut self) {
+        self.errors.clear();
+        self.runtime_errors.clear();
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.errors.push(diagnostic);
+    }
+
+    fn runtime_error(&mut self, err: RuntimeError) {
+        self.runtime_errors.push(err);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{token::Token, value::Value};
+use crate::{span::Span, token::Token, value::Value};
 
 #[derive(Debug, Clone)]
 pub struct BinaryExpression {
@@ -17,11 +17,13 @@ pub struct CallExpression {
 #[derive(Debug, Clone)]
 pub struct GroupingExpression {
     pub expr: Expression,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct LiteralExpression {
     pub value: Value,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -48,6 +50,19 @@ pub struct LogicalExpression {
     pub right: Expression,
 }
 
+#[derive(Debug, Clone)]
+pub struct GetExpression {
+    pub object: Expression,
+    pub name: Token,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetExpression {
+    pub object: Expression,
+    pub name: Token,
+    pub value: Expression,
+}
+
 #[derive(Debug, Clone)]
 pub enum Expression {
     Binary(Box<BinaryExpression>),
@@ -58,4 +73,24 @@ pub enum Expression {
     Variable(Box<VariableExpression>),
     Assign(Box<AssignExpression>),
     Logical(Box<LogicalExpression>),
+    Get(Box<GetExpression>),
+    Set(Box<SetExpression>),
+}
+
+impl Expression {
+    /// Returns the span covering the full source range of the expression.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Binary(x) => x.left.span().to(x.right.span()),
+            Self::Call(x) => x.callee.span().to(x.paren.span),
+            Self::Grouping(x) => x.span,
+            Self::Literal(x) => x.span,
+            Self::Unary(x) => x.operator.span.to(x.right.span()),
+            Self::Variable(x) => x.name.span,
+            Self::Assign(x) => x.name.span.to(x.value.span()),
+            Self::Logical(x) => x.left.span().to(x.right.span()),
+            Self::Get(x) => x.object.span().to(x.name.span),
+            Self::Set(x) => x.object.span().to(x.value.span()),
+        }
+    }
 }
@@ -1,39 +1,248 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    io::{BufRead, Write},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    builder::InterpreterBuilder,
+    bytecode::Program,
+    cancellation::CancellationToken,
+    compiler::Compiler,
+    convert::IntoNativeFunction,
+    diagnostic::Source,
     environment::{Env, Environment},
-    error::{get_err_handler, Result},
+    error::{ErrorKind, Result, RuntimeError, SharedErrorHandler},
     expression::{
-        AssignExpression, BinaryExpression, CallExpression, Expression, LogicalExpression,
-        UnaryExpression, VariableExpression,
+        AssignExpression, BinaryExpression, CallExpression, Expression, GetExpression,
+        LogicalExpression, SetExpression, UnaryExpression, VariableExpression,
     },
+    lexer::Lexer,
+    module::NativeModule,
+    object::{NativeObject, NativeType},
+    optimizer::Optimizer,
+    parser::Parser,
+    span::Span,
     statement::{
         BlockStatement, ExpressionStatement, FunctionStatement, IfStatement, PrintStatement,
         ReturnStatement, Statement, VarStatement, WhileStatement,
     },
-    token::{Token, TokenType},
-    value::{Function, NativeFunction, Value},
+    token::TokenType,
+    value::{Callable, Function, NativeFunction, Value},
+    vm,
 };
 
+/// How execution continues after a statement.
+pub(crate) enum Flow {
+    Normal,
+    Return(Value),
+    /// Returns the result of calling a ritual, which the caller runs in its place.
+    TailCall(TailCall),
+}
+
+/// A call in tail position, like `return f(x)`, that has been evaluated but not run yet.
+pub(crate) struct TailCall {
+    pub callee: Box<dyn Callable>,
+    pub args: Vec<Value>,
+    pub span: Span,
+}
+
+/// How scripts are executed. Both backends behave the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Walks the syntax tree directly.
+    #[default]
+    TreeWalker,
+    /// Compiles each statement to bytecode and runs it on a stack VM.
+    Bytecode,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterpreterConfig {
+    pub backend: Backend,
+    /// The maximum depth of nested calls, which stops runaway recursion with an error.
+    pub recursion_limit: usize,
+    pub instruction_budget: Option<u64>,
+    /// The wall-clock time a single run may take.
+    pub time_limit: Option<Duration>,
+    /// The approximate number of bytes a single run may allocate. Strings made by operators count,
+    /// as do the values natives return, lists and maps included. Allocations are added up as they
+    /// happen and never subtracted, and what natives allocate for themselves isn't seen.
+    pub allocation_limit: Option<usize>,
+    pub strict_concat: bool,
+    /// Run the optimizer over scripts before interpreting them.
+    pub optimize: bool,
+}
+
+/// Deep enough for recursive algorithms over a thousand or so items, while runaway recursion still
+/// fails fast. The tree-walker needs up to 8 KB of native stack per call in debug builds, so this
+/// many calls can take 16 MB, which the stack is grown by on demand (see `STACK_RED_ZONE`).
+pub const DEFAULT_RECURSION_LIMIT: usize = 2000;
+
+/// When less native stack than this is left before a call, the call runs on a new segment of
+/// `STACK_SEGMENT_SIZE` bytes. This keeps deep recursion from overflowing threads with small stacks.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT_SIZE: usize = 2 * 1024 * 1024;
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            instruction_budget: None,
+            time_limit: None,
+            allocation_limit: None,
+            strict_concat: false,
+            optimize: false,
+        }
+    }
+}
+
+/// How often the deadline is checked, in instructions, since reading the clock isn't free.
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
 pub struct Interpreter {
     globals: Env,
     env: Env,
+    err_handler: SharedErrorHandler,
+    output: Box<dyn Write>,
+    input: Box<dyn BufRead>,
+    config: InterpreterConfig,
+    cancellation: CancellationToken,
+    call_depth: usize,
+    instructions: u64,
+    allocated: usize,
+    deadline: Option<Instant>,
+    call_span: Option<Span>,
+    native_types: HashMap<TypeId, Rc<NativeType>>,
 }
 
 impl Interpreter {
-    pub fn new() -> Self {
+    /// Creates an interpreter that uses stdin and stdout. No budgets are set, and nested calls
+    /// are limited to `DEFAULT_RECURSION_LIMIT`.
+    pub fn new(err_handler: SharedErrorHandler) -> Self {
+        Self::builder().error_handler(err_handler).build()
+    }
+
+    /// Creates an interpreter that prints to `output`, such as an `OutputBuffer`.
+    pub fn with_output(err_handler: SharedErrorHandler, output: impl Write + 'static) -> Self {
+        Self::builder()
+            .error_handler(err_handler)
+            .output(output)
+            .build()
+    }
+
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::new()
+    }
+
+    pub(crate) fn from_parts(
+        err_handler: SharedErrorHandler,
+        output: Box<dyn Write>,
+        input: Box<dyn BufRead>,
+        config: InterpreterConfig,
+        cancellation: CancellationToken,
+    ) -> Self {
         let globals = Rc::new(RefCell::new(Environment::new(None)));
         Self {
             globals: globals.clone(),
             env: globals,
+            err_handler,
+            output,
+            input,
+            config,
+            cancellation,
+            call_depth: 0,
+            instructions: 0,
+            allocated: 0,
+            deadline: None,
+            call_span: None,
+            native_types: HashMap::new(),
         }
     }
 
+    /// Returns the sink that `$<` and printing natives write to.
+    pub fn get_output(&mut self) -> &mut dyn Write {
+        &mut *self.output
+    }
+
+    /// Returns the stream that reading natives read from.
+    pub fn get_input(&mut self) -> &mut dyn BufRead {
+        &mut *self.input
+    }
+
+    pub fn get_config(&self) -> &InterpreterConfig {
+        &self.config
+    }
+
+    /// Returns a handle that can stop running scripts, for example from another thread.
+    pub fn get_cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn define_global(&self, name: impl ToString, value: Value) {
+        self.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    pub fn get_error_handler(&self) -> SharedErrorHandler {
+        self.err_handler.clone()
+    }
+
     pub fn register_native(&self, func: NativeFunction) {
         let mut env = self.env.borrow_mut();
         env.define(func.get_name().to_owned(), Value::Callable(Box::new(func)));
     }
 
+    /// Registers a module as a global under its name, making its members reachable as `name.member`.
+    pub fn register_module(&self, module: NativeModule) {
+        self.globals
+            .borrow_mut()
+            .define(module.get_name().to_owned(), Value::Module(Rc::new(module)));
+    }
+
+    /// Registers an ordinary Rust function or closure as a native function.
+    /// The arity and argument conversions are derived from its signature.
+    pub fn register_fn<Args>(&self, name: impl ToString, func: impl IntoNativeFunction<Args>) {
+        self.register_native(func.into_native_function(name));
+    }
+
+    /// Registers the type used to expose values of `T` to scripts.
+    pub fn register_type<T: Any>(&mut self, native_type: NativeType) {
+        self.native_types
+            .insert(TypeId::of::<T>(), Rc::new(native_type));
+    }
+
+    /// Wraps `data` in an object of its registered type, or returns None if `T` isn't registered.
+    pub fn new_object<T: Any>(&self, data: T) -> Option<NativeObject> {
+        let native_type = self.native_types.get(&TypeId::of::<T>())?;
+        Some(NativeObject::new(native_type.clone(), data))
+    }
+
+    /// Returns the value of a global variable, such as a ritual defined by a script.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get_by_name(name)
+    }
+
+    /// Calls a callable value from the host with the given arguments, returning its result.
+    pub fn call(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value> {
+        if self.call_depth == 0 {
+            self.start_run();
+        }
+        self.call_value(callee.clone(), args, self.current_call_span())
+    }
+
+    fn current_call_span(&self) -> Span {
+        self.call_span.unwrap_or(Span::new(0, 0, 1, 1))
+    }
+
+    /// Creates an error pointing at the call currently being evaluated, for use in native functions.
+    pub fn call_error(&self, msg: impl ToString) -> RuntimeError {
+        RuntimeError::new(self.current_call_span(), msg)
+    }
+
     pub fn get_global_env(&self) -> Env {
         self.globals.clone()
     }
@@ -42,7 +251,7 @@ impl Interpreter {
         self.env.clone()
     }
 
-    fn is_truthy(val: &Value) -> bool {
+    pub(crate) fn is_truthy(val: &Value) -> bool {
         match val {
             Value::None => false,
             Value::Boolean(x) => *x,
@@ -50,19 +259,24 @@ impl Interpreter {
         }
     }
 
-    fn error<T>(token: Token, msg: impl ToString) -> Result<T> {
-        Err((token, msg).into())
+    pub(crate) fn error<T>(span: Span, msg: impl ToString) -> Result<T> {
+        Err((span, msg).into())
     }
 
     fn eval_unary(&mut self, expr: &UnaryExpression) -> Result<Value> {
         let right = self.evaluate(&expr.right)?;
-        let val = match expr.operator.token_type {
+        Self::unary_op(expr.operator.token_type, right, expr.operator.span)
+    }
+
+    /// Applies a unary operator, shared by both backends.
+    pub(crate) fn unary_op(operator: TokenType, right: Value, span: Span) -> Result<Value> {
+        let val = match operator {
             TokenType::Minus => {
                 let val = match right {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Minus unary operator can only be used on numbers.",
                         )
                     }
@@ -70,22 +284,14 @@ impl Interpreter {
                 Value::Number(-val)
             }
             TokenType::Not => Value::Boolean(!Self::is_truthy(&right)),
-            _ => {
-                return Self::error(
-                    expr.operator.clone(),
-                    "Minus unary operator can only be used on numbers.",
-                )
-            }
+            _ => return Self::error(span, "Minus unary operator can only be used on numbers."),
         };
         Ok(val)
     }
 
     fn is_equal(a: Value, b: Value) -> bool {
         match a {
-            Value::None => match b {
-                Value::None => true,
-                _ => false,
-            },
+            Value::None => matches!(b, Value::None),
             Value::Boolean(x) => match b {
                 Value::Boolean(y) => y == x,
                 _ => false,
@@ -98,6 +304,28 @@ impl Interpreter {
                 Value::String(y) => x == y,
                 _ => false,
             },
+            Value::List(x) => match b {
+                Value::List(y) => {
+                    x.len() == y.len() && x.into_iter().zip(y).all(|(x, y)| Self::is_equal(x, y))
+                }
+                _ => false,
+            },
+            Value::Map(x) => match b {
+                Value::Map(mut y) => {
+                    x.len() == y.len()
+                        && x.into_iter()
+                            .all(|(key, x)| y.remove(&key).is_some_and(|y| Self::is_equal(x, y)))
+                }
+                _ => false,
+            },
+            Value::Native(x) => match b {
+                Value::Native(y) => x.ptr_eq(&y),
+                _ => false,
+            },
+            Value::Module(x) => match b {
+                Value::Module(y) => Rc::ptr_eq(&x, &y),
+                _ => false,
+            },
             Value::Callable(_) => false,
         }
     }
@@ -105,13 +333,39 @@ impl Interpreter {
     fn eval_binary(&mut self, expr: &BinaryExpression) -> Result<Value> {
         let left = self.evaluate(&expr.left)?;
         let right = self.evaluate(&expr.right)?;
-        let val = match expr.operator.token_type {
+        self.binary_op(expr.operator.token_type, left, right, expr.operator.span)
+    }
+
+    /// Applies a binary operator, shared by both backends.
+    pub(crate) fn binary_op(
+        &mut self,
+        operator: TokenType,
+        left: Value,
+        right: Value,
+        span: Span,
+    ) -> Result<Value> {
+        let value = Self::apply_binary(operator, left, right, span, self.config.strict_concat)?;
+        if let Value::String(_) = value {
+            self.track_allocation(&value)?;
+        }
+        Ok(value)
+    }
+
+    /// Applies a binary operator without touching any interpreter state, so the optimizer can fold constants with it.
+    pub(crate) fn apply_binary(
+        operator: TokenType,
+        left: Value,
+        right: Value,
+        span: Span,
+        strict_concat: bool,
+    ) -> Result<Value> {
+        let val = match operator {
             TokenType::Minus => {
                 let left_val = match left {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Minus binary operator can only be used on numbers.",
                         )
                     }
@@ -120,7 +374,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Minus binary operator can only be used on numbers.",
                         )
                     }
@@ -132,7 +386,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Divide binary operator can only be used on numbers.",
                         )
                     }
@@ -141,7 +395,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Divide binary operator can only be used on numbers.",
                         )
                     }
@@ -153,7 +407,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Multiply binary operator can only be used on numbers.",
                         )
                     }
@@ -162,7 +416,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Multiply binary operator can only be used on numbers.",
                         )
                     }
@@ -173,16 +427,18 @@ impl Interpreter {
                 if let Value::String(x) = left {
                     if let Value::String(y) = right {
                         Value::String(x + &y)
+                    } else if strict_concat {
+                        return Self::error(
+                            span,
+                            format!("Cannot add {} to string.", right.type_name()),
+                        );
                     } else {
                         match right {
                             Value::Number(y) => Value::String(x + &y.to_string()),
                             Value::Boolean(y) => Value::String(x + &y.to_string()),
                             Value::None => Value::String(x + "none"),
                             _ => {
-                                return Self::error(
-                                    expr.operator.clone(),
-                                    "Unknown right operand in string concat.",
-                                )
+                                return Self::error(span, "Unknown right operand in string concat.")
                             }
                         }
                     }
@@ -190,14 +446,11 @@ impl Interpreter {
                     if let Value::Number(y) = right {
                         Value::Number(x + y)
                     } else {
-                        return Self::error(
-                            expr.operator.clone(),
-                            "Cannot add non-number to number.",
-                        );
+                        return Self::error(span, "Cannot add non-number to number.");
                     }
                 } else {
                     return Self::error(
-                        expr.operator.clone(),
+                        span,
                         "Plus binary operator can only be used with strings or numbers",
                     );
                 }
@@ -207,7 +460,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Greater binary operator can only be used on numbers.",
                         )
                     }
@@ -216,7 +469,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Greater binary operator can only be used on numbers.",
                         )
                     }
@@ -228,7 +481,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Greater-or-Equal binary operator can only be used on numbers.",
                         )
                     }
@@ -237,7 +490,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Greater-or-Equal binary operator can only be used on numbers.",
                         )
                     }
@@ -249,7 +502,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Less binary operator can only be used on numbers.",
                         )
                     }
@@ -258,7 +511,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Less binary operator can only be used on numbers.",
                         )
                     }
@@ -270,7 +523,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Less-or-Equal binary operator can only be used on numbers.",
                         )
                     }
@@ -279,7 +532,7 @@ impl Interpreter {
                     Value::Number(x) => x,
                     _ => {
                         return Self::error(
-                            expr.operator.clone(),
+                            span,
                             "Less-or-Equal binary operator can only be used on numbers.",
                         )
                     }
@@ -287,12 +540,7 @@ impl Interpreter {
                 Value::Boolean(left_val <= right_val)
             }
             TokenType::Is => Value::Boolean(Self::is_equal(left, right)),
-            _ => {
-                return Self::error(
-                    expr.operator.clone(),
-                    "Unknown operator in binary expression.",
-                )
-            }
+            _ => return Self::error(span, "Unknown operator in binary expression."),
         };
         Ok(val)
     }
@@ -318,113 +566,295 @@ impl Interpreter {
                 return Ok(left);
             }
         }
-        Ok(self.evaluate(&expr.right)?)
+        self.evaluate(&expr.right)
     }
 
-    fn eval_call(&mut self, expr: &CallExpression) -> Result<Value> {
-        let callee = self.evaluate(&expr.callee)?;
-        let mut args = vec![];
-        for arg in &expr.args {
-            args.push(self.evaluate(arg)?);
-        }
+    /// Checks that `callee` can be called with `arg_num` arguments.
+    pub(crate) fn check_callable(
+        callee: Value,
+        arg_num: usize,
+        call_span: Span,
+    ) -> Result<Box<dyn Callable>> {
         let callable = match callee {
             Value::Callable(x) => x,
-            _ => return Self::error(expr.paren.clone(), "Expected callable object."),
+            x => {
+                return Self::error(
+                    call_span,
+                    format!("Expected callable object, but got {}.", x.type_name()),
+                )
+            }
         };
-        let arg_num = args.len();
         let arg_needed = callable.get_arity();
         if arg_num != arg_needed {
             return Self::error(
-                expr.paren.clone(),
+                call_span,
                 format!("Exptected {} arguments, but got {}", arg_needed, arg_num),
             );
         }
-        callable.call(self, args)
+        Ok(callable)
+    }
+
+    /// Records that a call is starting, returning the span of the enclosing call to restore in `exit_call`.
+    pub(crate) fn enter_call(&mut self, call_span: Span) -> Result<Option<Span>> {
+        self.check_cancelled(call_span)?;
+        let limit = self.config.recursion_limit;
+        if self.call_depth >= limit {
+            let msg = format!("Stack overflow, calls were nested deeper than {limit}.");
+            return Err(RuntimeError::with_kind(
+                ErrorKind::StackOverflow,
+                call_span,
+                msg,
+            ));
+        }
+        self.call_depth += 1;
+        Ok(self.call_span.replace(call_span))
+    }
+
+    pub(crate) fn exit_call(&mut self, previous_span: Option<Span>) {
+        self.call_depth -= 1;
+        self.call_span = previous_span;
+    }
+
+    /// Returns whether a ritual is running, so calls in tail position can replace it.
+    pub(crate) fn in_call(&self) -> bool {
+        self.call_depth > 0
+    }
+
+    /// Records that the running ritual is being replaced by a tail call, which keeps the call depth the same.
+    pub(crate) fn tail_call(&mut self, call_span: Span) -> Result<()> {
+        self.check_cancelled(call_span)?;
+        self.call_span = Some(call_span);
+        Ok(())
+    }
+
+    pub(crate) fn call_value(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        call_span: Span,
+    ) -> Result<Value> {
+        let callable = Self::check_callable(callee, args.len(), call_span)?;
+        let previous_span = self.enter_call(call_span)?;
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || {
+            callable.call(self, args)
+        });
+        self.exit_call(previous_span);
+        result.map_err(|mut err| {
+            err.push_frame(callable.get_name(), call_span);
+            err
+        })
+    }
+
+    /// Evaluates the callee and arguments of a call, returning them with the span of the call.
+    fn eval_call_parts(&mut self, expr: &CallExpression) -> Result<(Value, Vec<Value>, Span)> {
+        let callee = self.evaluate(&expr.callee)?;
+        let mut args = vec![];
+        for arg in &expr.args {
+            args.push(self.evaluate(arg)?);
+        }
+        Ok((callee, args, expr.callee.span().to(expr.paren.span)))
+    }
+
+    fn eval_call(&mut self, expr: &CallExpression) -> Result<Value> {
+        let (callee, args, call_span) = self.eval_call_parts(expr)?;
+        self.call_value(callee, args, call_span)
+    }
+
+    fn eval_get(&mut self, expr: &GetExpression) -> Result<Value> {
+        match self.evaluate(&expr.object)? {
+            Value::Native(x) => x.get(self, &expr.name),
+            Value::Module(x) => x.get(&expr.name),
+            x => Self::error(
+                expr.name.span,
+                format!("Cannot access property on {}.", x.type_name()),
+            ),
+        }
+    }
+
+    fn eval_set(&mut self, expr: &SetExpression) -> Result<Value> {
+        let object = match self.evaluate(&expr.object)? {
+            Value::Native(x) => x,
+            x => {
+                return Self::error(
+                    expr.name.span,
+                    format!("Cannot set property on {}.", x.type_name()),
+                )
+            }
+        };
+        let value = self.evaluate(&expr.value)?;
+        object.set(self, &expr.name, value.clone())?;
+        Ok(value)
+    }
+
+    /// Counts one unit of work, and checks it against the instruction budget and the deadline.
+    /// The span is only worked out when a budget runs out, since finding the span of a large
+    /// expression means walking all of it.
+    pub(crate) fn tick(&mut self, span: impl FnOnce() -> Span) -> Result<()> {
+        self.instructions += 1;
+        if let Some(budget) = self.config.instruction_budget {
+            if self.instructions > budget {
+                let msg = format!("Exceeded the instruction budget of {budget}.");
+                return Err(RuntimeError::with_kind(
+                    ErrorKind::InstructionLimit,
+                    span(),
+                    msg,
+                ));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.instructions.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && Instant::now() >= deadline
+            {
+                let limit = self.config.time_limit.unwrap_or_default();
+                let msg = format!("Exceeded the time limit of {limit:?}.");
+                return Err(RuntimeError::with_kind(ErrorKind::TimeLimit, span(), msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the run if the cancellation token was set.
+    pub(crate) fn check_cancelled(&self, span: Span) -> Result<()> {
+        if self.cancellation.is_cancelled() {
+            let msg = "Execution was cancelled.";
+            return Err(RuntimeError::with_kind(ErrorKind::Cancelled, span, msg));
+        }
+        Ok(())
+    }
+
+    /// Counts a newly created value against the allocation limit.
+    pub(crate) fn track_allocation(&mut self, value: &Value) -> Result<()> {
+        self.allocated = self.allocated.saturating_add(value.heap_size());
+        match self.config.allocation_limit {
+            Some(limit) if self.allocated > limit => {
+                let msg = format!("Exceeded the allocation limit of {limit} bytes.");
+                let span = self.current_call_span();
+                Err(RuntimeError::with_kind(
+                    ErrorKind::AllocationLimit,
+                    span,
+                    msg,
+                ))
+            }
+            _ => Ok(()),
+        }
     }
 
     fn evaluate(&mut self, expr: &Expression) -> Result<Value> {
+        self.tick(|| expr.span())?;
         match expr {
             Expression::Literal(x) => Ok(x.value.clone()),
             Expression::Grouping(x) => self.evaluate(&x.expr),
-            Expression::Unary(x) => self.eval_unary(&*x),
-            Expression::Binary(x) => self.eval_binary(&*x),
-            Expression::Variable(x) => self.eval_variable(&*x),
-            Expression::Assign(x) => self.eval_assign(&*x),
-            Expression::Logical(x) => self.eval_logical(&*x),
-            Expression::Call(x) => self.eval_call(&*x),
+            Expression::Unary(x) => self.eval_unary(x),
+            Expression::Binary(x) => self.eval_binary(x),
+            Expression::Variable(x) => self.eval_variable(x),
+            Expression::Assign(x) => self.eval_assign(x),
+            Expression::Logical(x) => self.eval_logical(x),
+            Expression::Call(x) => self.eval_call(x),
+            Expression::Get(x) => self.eval_get(x),
+            Expression::Set(x) => self.eval_set(x),
         }
     }
 
-    fn execute_print_statement(&mut self, statement: &PrintStatement) -> Result<()> {
+    fn execute_print_statement(&mut self, statement: &PrintStatement) -> Result<Flow> {
         let val = self.evaluate(&statement.expr)?;
-        println!("{}", val);
-        Ok(())
+        if let Err(err) = writeln!(self.output, "{}", val) {
+            return Self::error(statement.span, format!("Could not write output: {err}"));
+        }
+        Ok(Flow::Normal)
     }
 
-    fn execute_expression_statement(&mut self, statement: &ExpressionStatement) -> Result<()> {
+    fn execute_expression_statement(&mut self, statement: &ExpressionStatement) -> Result<Flow> {
         self.evaluate(&statement.expr)?;
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn execute_var_statement(&mut self, statement: &VarStatement) -> Result<()> {
+    fn execute_var_statement(&mut self, statement: &VarStatement) -> Result<Flow> {
         let mut value = Value::None;
         if let Some(init) = &statement.initializer {
-            value = self.evaluate(&init)?;
+            value = self.evaluate(init)?;
         }
         self.env
             .borrow_mut()
             .define(statement.name.lexeme.clone(), value);
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn execute_function_statement(&mut self, statement: &FunctionStatement) -> Result<()> {
+    fn execute_function_statement(&mut self, statement: &FunctionStatement) -> Result<Flow> {
         let function = Function::new(statement.clone());
         self.env.borrow_mut().define(
             statement.name.lexeme.clone(),
             Value::Callable(Box::new(function)),
         );
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    pub fn execute_block(&mut self, statements: &[Statement], env: Env) -> Result<()> {
-        let previous = self.env.clone();
-        self.env = env;
+    /// Executes the statements in `env`, stopping early on errors and returns.
+    pub(crate) fn execute_block(&mut self, statements: &[Statement], env: Env) -> Result<Flow> {
+        let previous = std::mem::replace(&mut self.env, env);
+        let mut result = Ok(Flow::Normal);
         for statement in statements {
-            self.execute(statement).ok();
+            result = self.execute(statement);
+            if !matches!(result, Ok(Flow::Normal)) {
+                break;
+            }
         }
         self.env = previous;
-        Ok(())
+        result
     }
 
-    fn execute_block_statement(&mut self, statement: &BlockStatement) -> Result<()> {
+    fn execute_block_statement(&mut self, statement: &BlockStatement) -> Result<Flow> {
         self.execute_block(
             &statement.statements,
             Rc::new(RefCell::new(Environment::new(Some(self.env.clone())))),
         )
     }
 
-    fn execute_if_statement(&mut self, statement: &IfStatement) -> Result<()> {
+    fn execute_if_statement(&mut self, statement: &IfStatement) -> Result<Flow> {
         if Self::is_truthy(&self.evaluate(&statement.condition)?) {
-            self.execute_block_statement(&statement.then_branch)?;
+            self.execute_block_statement(&statement.then_branch)
         } else if let Some(x) = &statement.else_branch {
-            self.execute_block_statement(&x)?;
+            self.execute_block_statement(x)
+        } else {
+            Ok(Flow::Normal)
         }
-        Ok(())
     }
 
-    fn execute_while_statement(&mut self, statement: &WhileStatement) -> Result<()> {
+    fn execute_while_statement(&mut self, statement: &WhileStatement) -> Result<Flow> {
         while Self::is_truthy(&self.evaluate(&statement.condition)?) {
-            self.execute_block_statement(&statement.body)?;
+            match self.execute_block_statement(&statement.body)? {
+                Flow::Normal => {}
+                x => return Ok(x),
+            }
+            self.check_cancelled(statement.span)?;
         }
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn execute_return_statement(&mut self, statement: &ReturnStatement) -> Result<()> {
-        //TODO
-        todo!()
+    /// Calling another ritual is left to the ritual that is returning, so it doesn't nest.
+    /// Kept out of `execute_return_statement` so ordinary returns use less native stack.
+    #[inline(never)]
+    fn execute_tail_call(&mut self, expr: &CallExpression) -> Result<Flow> {
+        self.tick(|| expr.callee.span().to(expr.paren.span))?;
+        let (callee, args, span) = self.eval_call_parts(expr)?;
+        let callee = Self::check_callable(callee, args.len(), span)?;
+        if callee.as_function().is_some() {
+            return Ok(Flow::TailCall(TailCall { callee, args, span }));
+        }
+        let value = self.call_value(Value::Callable(callee), args, span)?;
+        Ok(Flow::Return(value))
+    }
+
+    fn execute_return_statement(&mut self, statement: &ReturnStatement) -> Result<Flow> {
+        let value = match &statement.expr {
+            Some(Expression::Call(x)) if self.in_call() => return self.execute_tail_call(x),
+            Some(x) => self.evaluate(x)?,
+            None => Value::None,
+        };
+        Ok(Flow::Return(value))
     }
 
-    fn execute(&mut self, statement: &Statement) -> Result<()> {
+    fn execute(&mut self, statement: &Statement) -> Result<Flow> {
+        self.tick(|| statement.span())?;
         match statement {
             Statement::Print(x) => self.execute_print_statement(x),
             Statement::Expression(x) => self.execute_expression_statement(x),
@@ -437,11 +867,94 @@ impl Interpreter {
         }
     }
 
-    pub fn interpret(&mut self, statements: Vec<Statement>) {
+    /// Resets the budgets, which apply to each run separately.
+    fn start_run(&mut self) {
+        self.instructions = 0;
+        self.allocated = 0;
+        self.deadline = self.config.time_limit.map(|x| Instant::now() + x);
+    }
+
+    /// Runs the statements, reporting script errors to the error handler and moving on to the next statement.
+    /// Errors that abort the whole run, like exceeding a budget or being cancelled, are returned instead.
+    pub fn interpret(&mut self, statements: Vec<Statement>) -> Result<()> {
+        let statements = if self.config.optimize {
+            Optimizer::new(&self.config).optimize(statements)
+        } else {
+            statements
+        };
+        self.start_run();
         for statement in statements {
-            if let Err(x) = self.execute(&statement) {
-                get_err_handler().runtime_error(x);
+            let result = match self.config.backend {
+                Backend::TreeWalker => self.execute(&statement).map(drop),
+                Backend::Bytecode => Compiler::compile_statement(&statement)
+                    .and_then(|proto| vm::run(self, proto, vec![]))
+                    .map(drop),
+            };
+            self.report_statement_error(result)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a compiled program on the VM, regardless of the configured backend.
+    /// Errors are handled the same way as in `interpret`.
+    pub fn run_program(&mut self, program: &Program) -> Result<()> {
+        self.start_run();
+        for proto in &program.statements {
+            let result = vm::run(self, proto.clone(), vec![]).map(drop);
+            self.report_statement_error(result)?;
+        }
+        Ok(())
+    }
+
+    /// Parses a script, first resetting the error handler and pointing it at `text`, then giving
+    /// it the syntax errors. Returns None if there were any. The script starts at the byte `start`
+    /// of `text`; the text before it is earlier input, like the previous lines of a REPL, which
+    /// errors raised in code from there still point into.
+    pub fn parse_source(
+        &mut self,
+        name: &str,
+        text: String,
+        start: usize,
+        strict: bool,
+    ) -> Option<Vec<Statement>> {
+        {
+            let mut err_handler = self.err_handler.borrow_mut();
+            err_handler.reset();
+            err_handler.set_source(Source::new(name, &text));
+        }
+        let lexer = Lexer::new(text)
+            .starting_at(start)
+            .strict_terminators(strict);
+        match Parser::new(lexer).parse() {
+            Ok(statements) => Some(statements),
+            Err(errors) => {
+                let mut err_handler = self.err_handler.borrow_mut();
+                for err in errors {
+                    err_handler.error(err);
+                }
+                None
+            }
+        }
+    }
+
+    /// Parses a script like `parse_source` and runs it, giving the error handler the error that
+    /// aborts the run too.
+    pub fn run_source(&mut self, name: &str, text: String, start: usize, strict: bool) {
+        let Some(statements) = self.parse_source(name, text, start, strict) else {
+            return;
+        };
+        if let Err(err) = self.interpret(statements) {
+            self.err_handler.borrow_mut().runtime_error(err);
+        }
+    }
+
+    fn report_statement_error(&mut self, result: Result<()>) -> Result<()> {
+        match result {
+            Err(x) if !x.is_fatal() => {
+                self.err_handler.borrow_mut().runtime_error(x);
+                Ok(())
             }
+            x => x,
         }
     }
 }
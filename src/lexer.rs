@@ -1,5 +1,5 @@
 use super::token::Token;
-use crate::{create_string_map, error::get_err_handler, token::TokenType, value::Value};
+use crate::{create_string_map, span::Span, token::TokenType, value::Value};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 
@@ -31,8 +31,17 @@ pub struct Lexer {
     start: usize,
     current: usize,
     line: usize,
+    column: usize,
+    start_line: usize,
+    start_column: usize,
     ignore_newline: bool,
     last_token: Option<Token>,
+    /// Whether the StatementEnd that closes the last statement at the end of the source was made.
+    ended: bool,
+    /// Whether whitespace, comments and newlines that don't end a statement are made into tokens.
+    trivia: bool,
+    /// Whether statements only end at a ';', instead of also at newlines where they could end.
+    strict: bool,
 }
 
 impl Lexer {
@@ -41,9 +50,45 @@ impl Lexer {
             source,
             start: 0,
             current: 0,
-            line: 0,
+            line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
             ignore_newline: false,
             last_token: None,
+            ended: false,
+            trivia: false,
+            strict: false,
+        }
+    }
+
+    /// Requires statements to be ended by a ';'. Newlines are then never statement ends, and the
+    /// last statement isn't ended for you at the end of the source. Blocks still end themselves.
+    pub fn strict_terminators(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Starts lexing at the byte `offset` of the source instead of at its beginning, so that
+    /// code appended to earlier source gets spans that point into the whole text.
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+        self.start = offset;
+        self.current = offset;
+        self.line = before.matches('\n').count() + 1;
+        self.column = before[line_start..].chars().count() + 1;
+        self.start_line = self.line;
+        self.start_column = self.column;
+        self
+    }
+
+    /// Creates a lexer that also makes tokens for whitespace, comments and newlines that don't
+    /// end a statement, so that joining the lexemes of all tokens gives back the source.
+    pub fn with_trivia(source: String) -> Self {
+        Self {
+            trivia: true,
+            ..Self::new(source)
         }
     }
 
@@ -51,42 +96,61 @@ impl Lexer {
         self.current >= self.source.len()
     }
 
-    fn get_current_char_unchecked(&self) -> char {
-        unsafe { self.source.chars().nth(self.current).unwrap_unchecked() }
+    /// Returns the characters from the cursor on. `current` is always a byte offset on a character boundary.
+    fn rest(&self) -> std::str::Chars<'_> {
+        self.source[self.current..].chars()
     }
 
     fn peek(&self) -> char {
-        if self.at_end() {
-            '\0'
-        } else {
-            self.get_current_char_unchecked()
-        }
+        self.rest().next().unwrap_or('\0')
     }
 
     fn peekpeek(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            unsafe { self.source.chars().nth(self.current + 1).unwrap_unchecked() }
-        }
+        self.rest().nth(1).unwrap_or('\0')
     }
 
+    /// Consumes the next character, or returns '\0' without moving at the end of the source.
     fn next_char(&mut self) -> char {
-        let ch = self.get_current_char_unchecked();
-        self.current += 1;
+        let Some(ch) = self.rest().next() else {
+            return '\0';
+        };
+        self.current += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         ch
     }
 
+    fn current_span(&self) -> Span {
+        Span::new(self.start, self.current, self.start_line, self.start_column)
+    }
+
+    fn end_span(&self) -> Span {
+        Span::new(self.current, self.current, self.line, self.column)
+    }
+
     fn make_token(&mut self, token_type: TokenType) -> Token {
         let text = self.source[self.start..self.current].to_owned();
-        let token = Token::new(token_type, text, Value::None, self.line);
-        token
+        Token::new(token_type, text, Value::None, self.current_span())
     }
 
     fn make_token_literal(&mut self, token_type: TokenType, literal: Value) -> Token {
         let text = self.source[self.start..self.current].to_owned();
-        let token = Token::new(token_type, text, literal, self.line);
-        token
+        Token::new(token_type, text, literal, self.current_span())
+    }
+
+    /// Makes a trivia token if trivia is being kept.
+    fn make_trivia(&mut self, token_type: TokenType) -> Option<Token> {
+        self.trivia.then(|| self.make_token(token_type))
+    }
+
+    /// Makes an error token for the text since the start of the token.
+    fn make_error(&mut self, msg: impl ToString) -> Token {
+        let text = self.source[self.start..self.current].to_owned();
+        Token::error(text, msg, self.current_span())
     }
 
     fn matches_next(&mut self, ch: char) -> bool {
@@ -97,35 +161,36 @@ impl Lexer {
             return false;
         }
 
-        self.current += 1;
-        return true;
+        self.next_char();
+        true
     }
 
-    const fn alphanumeric_or_underscore(ch: char) -> bool {
-        ch == '_' || ch.is_ascii_alphanumeric()
+    /// Identifiers follow the Unicode XID rules, and may also start with an underscore.
+    fn is_identifier_start(ch: char) -> bool {
+        ch == '_' || unicode_ident::is_xid_start(ch)
     }
 
-    fn handle_string(&mut self) -> Option<Token> {
+    fn is_identifier_continue(ch: char) -> bool {
+        unicode_ident::is_xid_continue(ch)
+    }
+
+    fn handle_string(&mut self) -> Token {
         while self.peek() != '"' && !self.at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
             self.next_char();
         }
 
         if self.at_end() {
-            get_err_handler().report(self.line, "Unterminated string.");
-            return None;
+            return self.make_error("Unterminated string, expected a closing '\"'.");
         }
 
         // Closing "
         self.next_char();
 
         let literal = self.source[self.start + 1..self.current - 1].to_owned();
-        Some(self.make_token_literal(TokenType::String, Value::String(literal)))
+        self.make_token_literal(TokenType::String, Value::String(literal))
     }
 
-    fn handle_number(&mut self) -> Option<Token> {
+    fn handle_number(&mut self) -> Token {
         while self.peek().is_ascii_digit() {
             self.next_char();
         }
@@ -138,18 +203,14 @@ impl Lexer {
             }
         }
 
-        let value = match self.source[self.start..self.current].parse::<f64>() {
-            Ok(x) => x,
-            Err(_) => {
-                get_err_handler().report(self.line, "Could not parse number!");
-                return None;
-            }
-        };
-        Some(self.make_token_literal(TokenType::Number, Value::Number(value)))
+        match self.source[self.start..self.current].parse::<f64>() {
+            Ok(x) => self.make_token_literal(TokenType::Number, Value::Number(x)),
+            Err(_) => self.make_error("Could not parse number!"),
+        }
     }
 
     fn handle_identifier(&mut self) -> Token {
-        while Self::alphanumeric_or_underscore(self.peek()) {
+        while Self::is_identifier_continue(self.peek()) {
             self.next_char();
         }
 
@@ -163,7 +224,7 @@ impl Lexer {
     }
 
     fn is_maybe_stmt_end(test_type: &TokenType) -> bool {
-        static STMT_END_TOKENS: &'static [TokenType] = &[
+        static STMT_END_TOKENS: &[TokenType] = &[
             TokenType::BraceClose,
             TokenType::ParenClose,
             TokenType::SquareClose,
@@ -174,35 +235,78 @@ impl Lexer {
             TokenType::None,
             TokenType::End,
             TokenType::Identifier,
+            TokenType::Error,
         ];
         STMT_END_TOKENS.iter().any(|x| x == test_type)
     }
 
+    /// Ends the last statement unless it already ended, then returns EOF for every call after
+    /// that. The StatementEnd covers no source, so its lexeme is empty.
+    fn lex_end(&mut self) -> Token {
+        self.start = self.current;
+        let unended = self
+            .last_token
+            .as_ref()
+            .is_some_and(|x| x.token_type != TokenType::StatementEnd);
+        if unended && !self.strict && !self.ended {
+            self.ended = true;
+            return Token::new(
+                TokenType::StatementEnd,
+                String::new(),
+                Value::None,
+                self.end_span(),
+            );
+        }
+        Token::new(
+            TokenType::EOF,
+            "EOF".to_owned(),
+            Value::None,
+            self.end_span(),
+        )
+    }
+
     fn lex_token(&mut self) -> Token {
+        loop {
+            if self.at_end() {
+                return self.lex_end();
+            }
+            if let Some(token) = self.scan_token() {
+                return token;
+            }
+        }
+    }
+
+    /// Scans the next piece of source, returning None if it was trivia that isn't being kept.
+    fn scan_token(&mut self) -> Option<Token> {
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
         let next = self.next_char();
-        match next {
+        let token = match next {
             '?' => {
                 // Skip line, is a comment.
                 while self.peek() != '\n' && !self.at_end() {
                     self.next_char();
                 }
-                self.lex_token()
+                return self.make_trivia(TokenType::Comment);
+            }
+            ' ' | '\r' | '\t' => {
+                while matches!(self.peek(), ' ' | '\r' | '\t') {
+                    self.next_char();
+                }
+                return self.make_trivia(TokenType::Whitespace);
             }
-            ' ' | '\r' | '\t' => self.lex_token(),
             '\n' => {
-                self.line += 1;
-                if !self.ignore_newline {
-                    let last = match &self.last_token {
-                        Some(x) => x,
-                        None => return self.lex_token(),
-                    };
-                    if !Self::is_maybe_stmt_end(&last.token_type) {
-                        return self.lex_token();
-                    }
-                    return self.make_token(TokenType::StatementEnd);
+                let ends_statement = !self.strict
+                    && !self.ignore_newline
+                    && self
+                        .last_token
+                        .as_ref()
+                        .is_some_and(|x| Self::is_maybe_stmt_end(&x.token_type));
+                if !ends_statement {
+                    return self.make_trivia(TokenType::Newline);
                 }
-                self.lex_token()
+                self.make_token(TokenType::StatementEnd)
             }
             '(' => {
                 self.ignore_newline = true;
@@ -223,6 +327,7 @@ impl Lexer {
             '{' => self.make_token(TokenType::BraceOpen),
             '}' => self.make_token(TokenType::BraceClose),
             ',' => self.make_token(TokenType::Comma),
+            ';' => self.make_token(TokenType::StatementEnd),
             '.' => self.make_token(TokenType::Dot),
             '-' => {
                 if self.matches_next('-') {
@@ -246,14 +351,13 @@ impl Lexer {
             '/' => self.make_token(TokenType::Divide),
             '=' => self.make_token(TokenType::Equal),
             '$' => {
-                let token = if self.matches_next('>') {
-                    TokenType::DollarGreater
+                if self.matches_next('>') {
+                    self.make_token(TokenType::DollarGreater)
                 } else if self.matches_next('<') {
-                    TokenType::DollarLess
+                    self.make_token(TokenType::DollarLess)
                 } else {
-                    return self.lex_token();
-                };
-                self.make_token(token)
+                    self.make_error("Expected '<' or '>' after '$'.")
+                }
             }
             '<' => {
                 let token = match self.matches_next('=') {
@@ -269,44 +373,21 @@ impl Lexer {
                 };
                 self.make_token(token)
             }
-            '"' => match self.handle_string() {
-                Some(x) => x,
-                None => self.lex_token(),
-            },
-            _ => {
-                if next.is_ascii_digit() {
-                    match self.handle_number() {
-                        Some(x) => return x,
-                        None => return self.lex_token(),
-                    }
-                } else if next.is_ascii_alphanumeric() {
-                    return self.handle_identifier();
-                }
-                self.lex_token()
-            }
-        }
+            '"' => self.handle_string(),
+            _ if next.is_ascii_digit() => self.handle_number(),
+            _ if Self::is_identifier_start(next) => self.handle_identifier(),
+            _ => self.make_error(format!("Unexpected character '{}'.", next.escape_debug())),
+        };
+        Some(token)
     }
 
     //TODO: Convert to iterator
     pub fn lex(&mut self) -> Token {
-        if self.at_end() {
-            if let Some(x) = &self.last_token {
-                let eof = Token::new(TokenType::EOF, "EOF".to_owned(), Value::None, self.line);
-                if let TokenType::EOF = x.token_type {
-                    return eof;
-                }
-                self.last_token = Some(eof);
-                return Token::new(
-                    TokenType::StatementEnd,
-                    "\n".to_owned(),
-                    Value::None,
-                    self.line,
-                );
-            }
-        }
-
         let token = self.lex_token();
-        self.last_token = Some(token.clone());
+        // Trivia doesn't decide whether a newline ends a statement.
+        if !token.token_type.is_trivia() {
+            self.last_token = Some(token.clone());
+        }
         token
     }
 }
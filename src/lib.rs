@@ -0,0 +1,48 @@
+pub mod builder;
+pub mod bytecode;
+pub mod cancellation;
+pub mod compiler;
+pub mod convert;
+pub mod cst;
+pub mod diagnostic;
+pub mod environment;
+pub mod error;
+pub mod expression;
+pub mod interpreter;
+pub mod lexer;
+pub mod module;
+pub mod object;
+pub mod optimizer;
+pub mod output;
+pub mod parser;
+pub mod repl;
+pub mod serialize;
+pub mod span;
+pub mod statement;
+pub mod stdlib;
+pub mod token;
+mod utils;
+pub mod value;
+pub mod vm;
+
+pub use builder::InterpreterBuilder;
+pub use cancellation::CancellationToken;
+pub use convert::{
+    ConversionError, FromArg, FromValue, IntoNativeFunction, IntoNativeResult, IntoValue,
+};
+pub use cst::SyntaxTree;
+pub use diagnostic::{Diagnostic, Renderer, Source};
+pub use error::{
+    CollectingErrorHandler, ErrorHandler, ErrorKind, RuntimeError, SharedErrorHandler,
+    StdErrorHandler, TraceFrame,
+};
+pub use interpreter::{Backend, Interpreter, InterpreterConfig};
+pub use lexer::Lexer;
+pub use module::NativeModule;
+pub use object::{NativeObject, NativeType};
+pub use optimizer::Optimizer;
+pub use output::OutputBuffer;
+pub use parser::Parser;
+pub use repl::Repl;
+pub use span::Span;
+pub use value::{Callable, Function, NativeFn, NativeFunction, Value};
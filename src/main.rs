@@ -1,53 +1,93 @@
-mod environment;
-mod error;
-mod expression;
-mod interpreter;
-mod lexer;
-mod parser;
-mod statement;
-mod token;
-mod utils;
-mod value;
-
-use interpreter::Interpreter;
-use lexer::Lexer;
-use parser::Parser;
+use cahlang_ast::{
+    compiler::Compiler, serialize, Backend, Interpreter, NativeFunction, Optimizer, Repl, Value,
+};
 use std::{
     env::args,
     fs::File,
-    io::{stdin, stdout, BufRead, Read, Write},
+    io::{stdin, stdout, BufRead, BufWriter, Read, Write},
+    path::Path,
+    process::exit,
 };
 
-use crate::value::{NativeFunction, Value};
-
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 const DEBUG_TEST_FILE: &str = include_str!("../test.cah");
-const RUN_DEBUG_FILE: bool = true;
+const USAGE: &str =
+    "Usage: cahlang-ast [--echo] [--debug-file] [--bytecode] [--compile] [--optimize] [--strict] [path]";
 
-fn get_source_path() -> Option<String> {
-    let mut args = args();
-    args.nth(1)
+#[derive(Default)]
+struct Options {
+    /// Print the source before running it.
+    echo: bool,
+    /// Run the bundled debug file instead of a path or the REPL.
+    debug_file: bool,
+    /// Run scripts on the bytecode VM instead of the tree-walker.
+    bytecode: bool,
+    /// Save the compiled bytecode of the script next to it instead of running it.
+    compile: bool,
+    /// Fold constants and remove dead code before running or compiling.
+    optimize: bool,
+    /// Require every statement to end with a ';'.
+    strict: bool,
+    path: Option<String>,
 }
 
-fn run(source: String, interpreter: &mut Interpreter) -> Result<()> {
-    interpreter.register_native(NativeFunction::new("hello_world".to_owned(), 0, |_, _| {
-        println!("Hello world!");
-        Ok(Value::None)
-    }));
+fn parse_args() -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    for arg in args().skip(1) {
+        match arg.as_str() {
+            "--echo" => options.echo = true,
+            "--debug-file" => options.debug_file = true,
+            "--bytecode" => options.bytecode = true,
+            "--compile" => options.compile = true,
+            "--optimize" => options.optimize = true,
+            "--strict" => options.strict = true,
+            x if x.starts_with("--") => return Err(format!("Unknown flag '{x}'\n{USAGE}")),
+            _ if options.path.is_some() => return Err(USAGE.to_owned()),
+            _ => options.path = Some(arg),
+        }
+    }
+    if options.compile && options.path.is_none() {
+        return Err(format!("--compile needs a path\n{USAGE}"));
+    }
+    Ok(options)
+}
 
-    println!("{}\n", source);
-    let lexer = Lexer::new(source);
-    let mut parser = Parser::new(lexer);
-    let statements = parser.parse();
-    interpreter.interpret(statements);
-    Ok(())
+/// Prints the source about to be run, if asked to.
+fn echo(source: &str, options: &Options) {
+    if options.echo {
+        println!("{}\n", source);
+    }
+}
+
+fn new_interpreter(options: &Options) -> Interpreter {
+    let backend = if options.bytecode {
+        Backend::Bytecode
+    } else {
+        Backend::TreeWalker
+    };
+    let interpreter = Interpreter::builder()
+        .stdlib()
+        .backend(backend)
+        .optimize(options.optimize)
+        .build();
+    interpreter.register_native(NativeFunction::new(
+        "hello_world".to_owned(),
+        0,
+        |interpreter, _| {
+            writeln!(interpreter.get_output(), "Hello world!")
+                .map_err(|err| interpreter.call_error(err))?;
+            Ok(Value::None)
+        },
+    ));
+    interpreter
 }
 
-fn run_interactively() -> Result<()> {
-    let mut interpreter = Interpreter::new();
+fn run_interactively(options: &Options) -> Result<()> {
+    let mut interpreter = new_interpreter(options);
     let mut stdout = stdout().lock();
     let mut stdin = stdin().lock();
+    let mut repl = Repl::new().strict_terminators(options.strict);
     let mut strbuf = String::new();
     loop {
         stdout.write_all(b"> ")?;
@@ -56,27 +96,95 @@ fn run_interactively() -> Result<()> {
         if count == 0 {
             break;
         }
-        run(strbuf.clone(), &mut interpreter).ok();
+        echo(&strbuf, options);
+        repl.run_line(&mut interpreter, &strbuf);
         strbuf.clear();
     }
     Ok(())
 }
 
-fn run_file(path: String) -> Result<()> {
-    let mut interpreter = Interpreter::new();
+/// Compiles a script and saves it with a `.cahc` extension.
+fn compile_file(path: &str, options: &Options) -> Result<()> {
+    let mut interpreter = new_interpreter(options);
     let mut buf = String::new();
     File::open(path)?.read_to_string(&mut buf)?;
-    run(buf, &mut interpreter)?;
+    echo(&buf, options);
+    let Some(statements) = interpreter.parse_source(path, buf, 0, options.strict) else {
+        return Ok(());
+    };
+    let statements = if options.optimize {
+        Optimizer::new(interpreter.get_config()).optimize(statements)
+    } else {
+        statements
+    };
+    let program = match Compiler::compile_program(&statements) {
+        Ok(x) => x,
+        Err(err) => {
+            interpreter
+                .get_error_handler()
+                .borrow_mut()
+                .runtime_error(err);
+            return Ok(());
+        }
+    };
+    let out_path = Path::new(path).with_extension("cahc");
+    let mut out = BufWriter::new(File::create(&out_path)?);
+    serialize::write_program(&program, &mut out)?;
+    out.flush()?;
+    println!("Compiled to {}", out_path.display());
+    Ok(())
+}
+
+/// Runs a script saved by `compile_file` on the VM.
+fn run_compiled(path: &str, options: &Options) -> Result<()> {
+    let mut interpreter = new_interpreter(options);
+    let program = serialize::read_program(&mut File::open(path)?)?;
+    interpreter.get_error_handler().borrow_mut().reset();
+    if let Err(err) = interpreter.run_program(&program) {
+        interpreter
+            .get_error_handler()
+            .borrow_mut()
+            .runtime_error(err);
+    }
+    Ok(())
+}
+
+fn run_file(path: &str, options: &Options) -> Result<()> {
+    if options.compile {
+        return compile_file(path, options);
+    }
+    if Path::new(path).extension().is_some_and(|x| x == "cahc") {
+        return run_compiled(path, options);
+    }
+    let mut interpreter = new_interpreter(options);
+    let mut buf = String::new();
+    File::open(path)?.read_to_string(&mut buf)?;
+    echo(&buf, options);
+    interpreter.run_source(path, buf, 0, options.strict);
     Ok(())
 }
 
 fn main() -> Result<()> {
-    if RUN_DEBUG_FILE {
-        let mut intr = Interpreter::new();
-        return run(DEBUG_TEST_FILE.to_owned(), &mut intr);
+    let options = match parse_args() {
+        Ok(x) => x,
+        Err(msg) => {
+            eprintln!("{msg}");
+            exit(2);
+        }
+    };
+    if options.debug_file {
+        let mut intr = new_interpreter(&options);
+        echo(DEBUG_TEST_FILE, &options);
+        intr.run_source("test.cah", DEBUG_TEST_FILE.to_owned(), 0, options.strict);
+        return Ok(());
     }
-    match get_source_path() {
-        Some(x) => run_file(x),
-        None => run_interactively(),
+    let result = match &options.path {
+        Some(x) => run_file(x, &options),
+        None => run_interactively(&options),
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        exit(1);
     }
+    Ok(())
 }
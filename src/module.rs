@@ -0,0 +1,60 @@
+use crate::{
+    convert::IntoNativeFunction,
+    error::{Result, RuntimeError},
+    token::Token,
+    value::{Callable, NativeFunction, Value},
+};
+use std::collections::HashMap;
+
+/// A named bundle of functions and constants, accessed by scripts as `name.member`.
+#[derive(Debug, Clone)]
+pub struct NativeModule {
+    name: String,
+    members: HashMap<String, Value>,
+}
+
+impl NativeModule {
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn with_native(mut self, func: NativeFunction) -> Self {
+        self.members
+            .insert(func.get_name().to_owned(), Value::Callable(Box::new(func)));
+        self
+    }
+
+    /// Adds an ordinary Rust function or closure, converting it like `Interpreter::register_fn`.
+    pub fn with_fn<Args>(self, name: impl ToString, func: impl IntoNativeFunction<Args>) -> Self {
+        self.with_native(func.into_native_function(name))
+    }
+
+    pub fn with_constant(mut self, name: impl ToString, value: Value) -> Self {
+        self.members.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn get_member(&self, name: &str) -> Option<&Value> {
+        self.members.get(name)
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value> {
+        match self.members.get(&name.lexeme) {
+            Some(x) => Ok(x.clone()),
+            None => {
+                let msg = format!(
+                    "Undefined member '{}' in module '{}'",
+                    name.lexeme, self.name
+                );
+                Err(RuntimeError::new(name.span, msg))
+            }
+        }
+    }
+}
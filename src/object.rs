@@ -0,0 +1,242 @@
+use crate::{
+    convert::{ConversionError, FromValue, IntoValue},
+    error::{Result, RuntimeError},
+    interpreter::Interpreter,
+    token::Token,
+    value::{Callable, CallableClone, Value},
+};
+use std::{
+    any::{Any, TypeId},
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    fmt::Debug,
+    rc::Rc,
+};
+
+pub type NativeMethodFn = Rc<dyn Fn(&mut Interpreter, &NativeObject, Vec<Value>) -> Result<Value>>;
+pub type NativeGetterFn = Rc<dyn Fn(&mut Interpreter, &NativeObject) -> Result<Value>>;
+pub type NativeSetterFn = Rc<dyn Fn(&mut Interpreter, &NativeObject, Value) -> Result<()>>;
+
+#[derive(Clone)]
+struct NativeMethod {
+    name: String,
+    arg_count: usize,
+    func: NativeMethodFn,
+}
+
+#[derive(Clone)]
+struct NativeProperty {
+    getter: NativeGetterFn,
+    setter: Option<NativeSetterFn>,
+}
+
+/// Describes a Rust type exposed to scripts, with the methods and properties reachable through `.`.
+pub struct NativeType {
+    name: String,
+    methods: HashMap<String, NativeMethod>,
+    properties: HashMap<String, NativeProperty>,
+}
+
+impl NativeType {
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            methods: HashMap::new(),
+            properties: HashMap::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn with_method(
+        mut self,
+        name: impl ToString,
+        arg_count: usize,
+        func: impl Fn(&mut Interpreter, &NativeObject, Vec<Value>) -> Result<Value> + 'static,
+    ) -> Self {
+        let method = NativeMethod {
+            name: name.to_string(),
+            arg_count,
+            func: Rc::new(func),
+        };
+        self.methods.insert(method.name.clone(), method);
+        self
+    }
+
+    /// Adds a property that scripts can read but not assign to.
+    pub fn with_property(
+        mut self,
+        name: impl ToString,
+        getter: impl Fn(&mut Interpreter, &NativeObject) -> Result<Value> + 'static,
+    ) -> Self {
+        let property = NativeProperty {
+            getter: Rc::new(getter),
+            setter: None,
+        };
+        self.properties.insert(name.to_string(), property);
+        self
+    }
+
+    /// Adds a property that scripts can both read and assign to.
+    pub fn with_mut_property(
+        mut self,
+        name: impl ToString,
+        getter: impl Fn(&mut Interpreter, &NativeObject) -> Result<Value> + 'static,
+        setter: impl Fn(&mut Interpreter, &NativeObject, Value) -> Result<()> + 'static,
+    ) -> Self {
+        let property = NativeProperty {
+            getter: Rc::new(getter),
+            setter: Some(Rc::new(setter)),
+        };
+        self.properties.insert(name.to_string(), property);
+        self
+    }
+}
+
+/// An opaque Rust value handed to scripts, along with the type that describes it.
+#[derive(Clone)]
+pub struct NativeObject {
+    native_type: Rc<NativeType>,
+    /// The type of `data`, which can be checked while `data` is borrowed.
+    type_id: TypeId,
+    data: Rc<RefCell<dyn Any>>,
+}
+
+impl NativeObject {
+    pub fn new<T: Any>(native_type: Rc<NativeType>, data: T) -> Self {
+        Self {
+            native_type,
+            type_id: TypeId::of::<T>(),
+            data: Rc::new(RefCell::new(data)),
+        }
+    }
+
+    pub fn get_type_name(&self) -> &str {
+        self.native_type.get_name()
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    /// Borrows the wrapped value, if it is a `T` that isn't mutably borrowed already.
+    pub fn downcast_ref<T: Any>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.data.try_borrow().ok()?, |x| x.downcast_ref::<T>()).ok()
+    }
+
+    /// Mutably borrows the wrapped value, if it is a `T` that isn't borrowed already, like when
+    /// a script passes an object to one of its own methods.
+    pub fn downcast_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.data.try_borrow_mut().ok()?, |x| x.downcast_mut::<T>()).ok()
+    }
+
+    /// Returns true if both objects wrap the same value.
+    pub fn ptr_eq(&self, other: &NativeObject) -> bool {
+        Rc::ptr_eq(&self.data, &other.data)
+    }
+
+    fn undefined_property<T>(&self, name: &Token) -> Result<T> {
+        let msg = format!(
+            "Undefined property '{}' on {}",
+            name.lexeme,
+            self.get_type_name()
+        );
+        Err(RuntimeError::new(name.span, msg))
+    }
+
+    pub fn get(&self, interpreter: &mut Interpreter, name: &Token) -> Result<Value> {
+        if let Some(property) = self.native_type.properties.get(&name.lexeme) {
+            let value = (property.getter)(interpreter, self)?;
+            interpreter.track_allocation(&value)?;
+            return Ok(value);
+        }
+        if let Some(method) = self.native_type.methods.get(&name.lexeme) {
+            return Ok(Value::Callable(Box::new(BoundNativeMethod {
+                this: self.clone(),
+                method: method.clone(),
+            })));
+        }
+        self.undefined_property(name)
+    }
+
+    pub fn set(&self, interpreter: &mut Interpreter, name: &Token, value: Value) -> Result<()> {
+        let property = match self.native_type.properties.get(&name.lexeme) {
+            Some(x) => x,
+            None => return self.undefined_property(name),
+        };
+        match &property.setter {
+            Some(setter) => setter(interpreter, self, value),
+            None => {
+                let msg = format!(
+                    "Property '{}' on {} is read-only",
+                    name.lexeme,
+                    self.get_type_name()
+                );
+                Err(RuntimeError::new(name.span, msg))
+            }
+        }
+    }
+}
+
+impl Debug for NativeObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeObject")
+            .field("type", &self.get_type_name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromValue for NativeObject {
+    fn from_value(value: Value) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::Native(x) => Ok(x),
+            x => Err(ConversionError::new("native object", &x)),
+        }
+    }
+}
+
+impl IntoValue for NativeObject {
+    fn into_value(self) -> Value {
+        Value::Native(self)
+    }
+}
+
+/// A native method looked up on an object, which remembers the object it was looked up on.
+#[derive(Clone)]
+struct BoundNativeMethod {
+    this: NativeObject,
+    method: NativeMethod,
+}
+
+impl Debug for BoundNativeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoundNativeMethod")
+            .field("this", &self.this)
+            .field("arg_count", &self.method.arg_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallableClone for BoundNativeMethod {
+    fn clone_box(&self) -> Box<dyn Callable> {
+        Box::new(self.clone())
+    }
+}
+
+impl Callable for BoundNativeMethod {
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value> {
+        let value = (self.method.func)(interpreter, &self.this, args)?;
+        interpreter.track_allocation(&value)?;
+        Ok(value)
+    }
+
+    fn get_arity(&self) -> usize {
+        self.method.arg_count
+    }
+
+    fn get_name(&self) -> &str {
+        &self.method.name
+    }
+}
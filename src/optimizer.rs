@@ -0,0 +1,233 @@
+use crate::{
+    expression::{
+        AssignExpression, BinaryExpression, CallExpression, Expression, GetExpression,
+        GroupingExpression, LiteralExpression, LogicalExpression, SetExpression, UnaryExpression,
+    },
+    interpreter::{Interpreter, InterpreterConfig},
+    statement::{BlockStatement, FunctionStatement, IfStatement, Statement, WhileStatement},
+    token::TokenType,
+    value::Value,
+};
+
+/// Simplifies parsed statements before they run, without changing what they do.
+///
+/// Operators on literals are folded into a single literal, `if` and `while` statements
+/// with a constant condition are replaced by the branch that runs, and statements after
+/// a block or `if` that returns on every path are dropped. Expressions that would fail
+/// are left alone, so they still report their error when they run.
+pub struct Optimizer {
+    strict_concat: bool,
+}
+
+impl Optimizer {
+    /// Creates an optimizer that folds with the same semantics as an interpreter with the given config.
+    pub fn new(config: &InterpreterConfig) -> Self {
+        Self {
+            strict_concat: config.strict_concat,
+        }
+    }
+
+    /// Optimizes a whole script. Every top-level statement runs on its own, so returning
+    /// from one doesn't make the statements after it unreachable.
+    pub fn optimize(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        statements
+            .into_iter()
+            .filter_map(|x| self.statement(x))
+            .collect()
+    }
+
+    /// Optimizes the statements of a block, dropping those that can never run.
+    fn block(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        let mut out = vec![];
+        for statement in statements {
+            let Some(statement) = self.statement(statement) else {
+                continue;
+            };
+            let returns = Self::always_returns(&statement);
+            out.push(statement);
+            if returns {
+                break;
+            }
+        }
+        out
+    }
+
+    fn always_returns(statement: &Statement) -> bool {
+        match statement {
+            Statement::Return(_) => true,
+            Statement::Block(x) => x.statements.last().is_some_and(Self::always_returns),
+            Statement::If(x) => {
+                let returns =
+                    |x: &BlockStatement| x.statements.last().is_some_and(Self::always_returns);
+                returns(&x.then_branch) && x.else_branch.as_ref().is_some_and(returns)
+            }
+            _ => false,
+        }
+    }
+
+    fn block_statement(&self, block: BlockStatement) -> BlockStatement {
+        BlockStatement {
+            statements: self.block(block.statements),
+            span: block.span,
+        }
+    }
+
+    /// Returns the optimized statement, or None if it does nothing.
+    fn statement(&self, statement: Statement) -> Option<Statement> {
+        let statement = match statement {
+            Statement::Expression(mut x) => {
+                x.expr = self.expression(x.expr);
+                Statement::Expression(x)
+            }
+            Statement::Print(mut x) => {
+                x.expr = self.expression(x.expr);
+                Statement::Print(x)
+            }
+            Statement::Var(mut x) => {
+                x.initializer = x.initializer.map(|x| self.expression(x));
+                Statement::Var(x)
+            }
+            Statement::Function(x) => Statement::Function(FunctionStatement {
+                body: self.block(x.body),
+                ..x
+            }),
+            Statement::Block(x) => Statement::Block(self.block_statement(x)),
+            Statement::If(x) => {
+                let condition = self.expression(x.condition);
+                let then_branch = self.block_statement(x.then_branch);
+                let else_branch = x.else_branch.map(|x| self.block_statement(x));
+                match Self::literal(&condition) {
+                    Some(value) if Interpreter::is_truthy(value) => Statement::Block(then_branch),
+                    Some(_) => Statement::Block(else_branch?),
+                    None => Statement::If(IfStatement {
+                        condition,
+                        then_branch,
+                        else_branch,
+                        span: x.span,
+                    }),
+                }
+            }
+            Statement::While(x) => {
+                let condition = self.expression(x.condition);
+                if Self::literal(&condition).is_some_and(|x| !Interpreter::is_truthy(x)) {
+                    return None;
+                }
+                Statement::While(WhileStatement {
+                    condition,
+                    body: self.block_statement(x.body),
+                    span: x.span,
+                })
+            }
+            Statement::Return(mut x) => {
+                x.expr = x.expr.map(|x| self.expression(x));
+                Statement::Return(x)
+            }
+        };
+        Some(statement)
+    }
+
+    fn literal(expr: &Expression) -> Option<&Value> {
+        match expr {
+            Expression::Literal(x) => Some(&x.value),
+            _ => None,
+        }
+    }
+
+    /// Replaces an expression with a literal, keeping its span for error messages.
+    fn fold(expr: &Expression, value: Value) -> Expression {
+        Expression::Literal(Box::new(LiteralExpression {
+            value,
+            span: expr.span(),
+        }))
+    }
+
+    fn expression(&self, expr: Expression) -> Expression {
+        match expr {
+            Expression::Literal(_) | Expression::Variable(_) => expr,
+            Expression::Grouping(x) => {
+                let inner = self.expression(x.expr);
+                match Self::literal(&inner) {
+                    Some(value) => Expression::Literal(Box::new(LiteralExpression {
+                        value: value.clone(),
+                        span: x.span,
+                    })),
+                    None => Expression::Grouping(Box::new(GroupingExpression {
+                        expr: inner,
+                        span: x.span,
+                    })),
+                }
+            }
+            Expression::Unary(x) => {
+                let x = UnaryExpression {
+                    right: self.expression(x.right),
+                    ..*x
+                };
+                let folded = Self::literal(&x.right).and_then(|value| {
+                    Interpreter::unary_op(x.operator.token_type, value.clone(), x.operator.span)
+                        .ok()
+                });
+                let expr = Expression::Unary(Box::new(x));
+                match folded {
+                    Some(value) => Self::fold(&expr, value),
+                    None => expr,
+                }
+            }
+            Expression::Binary(x) => {
+                let x = BinaryExpression {
+                    left: self.expression(x.left),
+                    right: self.expression(x.right),
+                    ..*x
+                };
+                let folded = match (Self::literal(&x.left), Self::literal(&x.right)) {
+                    (Some(left), Some(right)) => Interpreter::apply_binary(
+                        x.operator.token_type,
+                        left.clone(),
+                        right.clone(),
+                        x.operator.span,
+                        self.strict_concat,
+                    )
+                    .ok(),
+                    _ => None,
+                };
+                let expr = Expression::Binary(Box::new(x));
+                match folded {
+                    Some(value) => Self::fold(&expr, value),
+                    None => expr,
+                }
+            }
+            Expression::Logical(x) => {
+                let left = self.expression(x.left);
+                let right = self.expression(x.right);
+                // A constant left side decides whether the right side runs, and the result is
+                // whichever side was evaluated last.
+                match Self::literal(&left).map(Interpreter::is_truthy) {
+                    Some(truthy) if truthy == (x.operator.token_type == TokenType::Or) => left,
+                    Some(_) => right,
+                    None => Expression::Logical(Box::new(LogicalExpression {
+                        left,
+                        operator: x.operator,
+                        right,
+                    })),
+                }
+            }
+            Expression::Assign(x) => Expression::Assign(Box::new(AssignExpression {
+                value: self.expression(x.value),
+                ..*x
+            })),
+            Expression::Call(x) => Expression::Call(Box::new(CallExpression {
+                callee: self.expression(x.callee),
+                args: x.args.into_iter().map(|x| self.expression(x)).collect(),
+                ..*x
+            })),
+            Expression::Get(x) => Expression::Get(Box::new(GetExpression {
+                object: self.expression(x.object),
+                ..*x
+            })),
+            Expression::Set(x) => Expression::Set(Box::new(SetExpression {
+                object: self.expression(x.object),
+                value: self.expression(x.value),
+                ..*x
+            })),
+        }
+    }
+}
@@ -1,12 +1,14 @@
-use core::panic;
 use std::{fmt::Display, iter::Peekable};
 
 use crate::{
-    error::{get_err_handler, Result, RuntimeError},
+    cst::{SyntaxKind, SyntaxNode, TreeBuilder},
+    diagnostic::Diagnostic,
     expression::{
-        AssignExpression, BinaryExpression, CallExpression, Expression, GroupingExpression,
-        LiteralExpression, LogicalExpression, UnaryExpression, VariableExpression,
+        AssignExpression, BinaryExpression, CallExpression, Expression, GetExpression,
+        GroupingExpression, LiteralExpression, LogicalExpression, SetExpression, UnaryExpression,
+        VariableExpression,
     },
+    span::Span,
     statement::{
         BlockStatement, ExpressionStatement, FunctionStatement, IfStatement, PrintStatement,
         ReturnStatement, Statement, VarStatement, WhileStatement,
@@ -16,17 +18,36 @@ use crate::{
 };
 
 const MAX_FUNC_ARG_COUNT: usize = 255;
+/// How deeply expressions and blocks may nest, so that parsing and evaluating them can't overflow the stack.
+const MAX_NESTING_DEPTH: usize = 128;
+
+type Result<T> = std::result::Result<T, Box<Diagnostic>>;
 
 enum FunctionKind {
     Function,
-    Method,
+}
+
+/// What closed a block.
+enum BlockClose {
+    Brace,
+    End,
+    /// The `else` of an `if`, which is left for the `if` to consume.
+    Else,
+}
+
+/// The kind of block being parsed, which decides the tokens that close it.
+#[derive(Clone, Copy)]
+enum OpenBlock {
+    Brace,
+    End,
+    /// The first branch of an `if`, which an `else` closes too.
+    EndOrElse,
 }
 
 impl Display for FunctionKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let text = match self {
             Self::Function => "Function",
-            Self::Method => "Method",
         };
         f.write_str(text)
     }
@@ -35,6 +56,12 @@ impl Display for FunctionKind {
 pub struct Parser<I: Iterator<Item = Token>> {
     tokens: Peekable<I>,
     last_token: Option<Token>,
+    errors: Vec<Diagnostic>,
+    /// The blocks being parsed, innermost last.
+    open_blocks: Vec<OpenBlock>,
+    nesting_depth: usize,
+    /// Collects every token, trivia included, when building a syntax tree.
+    tree: Option<TreeBuilder>,
 }
 
 impl<I: Iterator<Item = Token>> Parser<I> {
@@ -42,12 +69,90 @@ impl<I: Iterator<Item = Token>> Parser<I> {
         Self {
             tokens: tokens.peekable(),
             last_token: None,
+            errors: vec![],
+            open_blocks: vec![],
+            nesting_depth: 0,
+            tree: None,
+        }
+    }
+
+    /// Creates a parser that also builds a lossless syntax tree from tokens that include trivia.
+    pub(crate) fn with_syntax_tree(tokens: I) -> Self {
+        Self {
+            tree: Some(TreeBuilder::default()),
+            ..Self::new(tokens)
+        }
+    }
+
+    /// Returns the syntax tree of everything parsed, taking any tokens left before the end with it.
+    pub(crate) fn finish_syntax_tree(&mut self) -> SyntaxNode {
+        while !self.at_end() {
+            self.advance();
+        }
+        self.tree.take().unwrap_or_default().finish()
+    }
+
+    /// Marks where a syntax node may start. Does nothing unless a syntax tree is being built.
+    fn checkpoint(&mut self) -> usize {
+        self.skip_trivia();
+        self.tree.as_ref().map_or(0, TreeBuilder::checkpoint)
+    }
+
+    /// Wraps everything consumed since `checkpoint` into a syntax node.
+    fn wrap(&mut self, checkpoint: usize, kind: SyntaxKind) {
+        if let Some(tree) = &mut self.tree {
+            tree.wrap(checkpoint, kind);
+        }
+    }
+
+    /// Moves past trivia, which only the syntax tree keeps.
+    fn skip_trivia(&mut self) {
+        while let Some(token) = self.tokens.next_if(|x| x.token_type.is_trivia()) {
+            if let Some(tree) = &mut self.tree {
+                tree.token(token);
+            }
         }
     }
 
-    fn error<T>(token: &Token, msg: &str) -> Result<T> {
-        get_err_handler().report(token.line, msg);
-        Err(RuntimeError::new(token.clone(), msg))
+    fn error<T>(span: Span, msg: &str) -> Result<T> {
+        Err(Box::new(Diagnostic::error(span, msg)))
+    }
+
+    /// Makes an error at the next token. If the lexer couldn't make sense of that token, its
+    /// message is used instead, since it says more than what the parser expected there.
+    fn error_at_next(&mut self, msg: &str) -> Diagnostic {
+        let token = self.peek();
+        let msg = token.error_message().unwrap_or(msg);
+        Diagnostic::error(token.span, msg)
+    }
+
+    /// Runs `parse` one nesting level deeper, failing instead if the code is nested too deeply.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.chained(|parser| {
+            parser.deepen()?;
+            parse(parser)
+        })
+    }
+
+    /// Runs `parse`, then drops the nesting levels it went down with [`Parser::deepen`].
+    fn chained<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let depth = self.nesting_depth;
+        let result = parse(self);
+        self.nesting_depth = depth;
+        result
+    }
+
+    /// Goes one nesting level deeper until the enclosing [`Parser::chained`] returns. Chains
+    /// like `a + b + c` or `a.b.c` nest one level deeper with every link, so each link counts
+    /// towards [`MAX_NESTING_DEPTH`] like a pair of parentheses does.
+    fn deepen(&mut self) -> Result<()> {
+        if self.nesting_depth >= MAX_NESTING_DEPTH {
+            let diagnostic = Diagnostic::error(self.peek().span, "Code is nested too deeply.")
+                .with_help("Split the code up using variables or rituals.");
+            return Err(Box::new(diagnostic));
+        }
+        self.nesting_depth += 1;
+        Ok(())
     }
 
     fn check(&mut self, typ: TokenType) -> bool {
@@ -59,6 +164,7 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     }
 
     fn peek(&mut self) -> &Token {
+        self.skip_trivia();
         self.tokens.peek().unwrap()
     }
 
@@ -73,8 +179,11 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     fn advance(&mut self) -> Token {
         let ret = self.peek().clone();
         let next = self.tokens.next().unwrap();
+        if let Some(tree) = &mut self.tree {
+            tree.token(next.clone());
+        }
         self.last_token = Some(next);
-        return ret;
+        ret
     }
 
     fn match_next(&mut self, types: &[TokenType]) -> bool {
@@ -87,18 +196,66 @@ impl<I: Iterator<Item = Token>> Parser<I> {
         false
     }
 
+    /// Whether the next token closes the innermost block being parsed.
+    fn at_block_close(&mut self) -> bool {
+        let Some(&block) = self.open_blocks.last() else {
+            return false;
+        };
+        match self.peek().token_type {
+            TokenType::BraceClose => matches!(block, OpenBlock::Brace),
+            TokenType::End => !matches!(block, OpenBlock::Brace),
+            TokenType::Else => matches!(block, OpenBlock::EndOrElse),
+            _ => false,
+        }
+    }
+
+    /// Skips tokens until the start of what is likely the next statement.
     fn synchronize(&mut self) {
-        self.advance();
         while !self.at_end() {
-            if self.previous().token_type == TokenType::StatementEnd {
+            // Leave the closer of the innermost block for that block. Any other '}', `end` or
+            // `else` is skipped like the rest of the broken code.
+            if self.at_block_close() {
                 return;
             }
-
-            // Skip through untill a statement end is hit, then advance.
-            if self.peek().token_type == TokenType::StatementEnd {
-                self.advance();
+            let token = self.advance();
+            // Lexical errors and stray closers in the skipped code are still reported, unless the
+            // error being recovered from was already about this token.
+            let msg = match token.token_type {
+                TokenType::BraceClose | TokenType::End | TokenType::Else => {
+                    Some(format!("Unexpected '{}'.", token.lexeme))
+                }
+                _ => token.error_message().map(str::to_owned),
+            };
+            if let Some(msg) = msg {
+                if self.errors.last().map(|x| x.span) != Some(token.span) {
+                    self.errors.push(Diagnostic::error(token.span, msg));
+                }
+            }
+            if token.token_type == TokenType::StatementEnd {
                 return;
             }
+            match self.peek().token_type {
+                TokenType::Ritual
+                | TokenType::Offering
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Return
+                | TokenType::DollarLess => return,
+                _ => (),
+            }
+        }
+    }
+
+    /// Skips empty statements, such as blank lines.
+    fn skip_statement_ends(&mut self) {
+        while self.match_next(&[TokenType::StatementEnd]) {}
+    }
+
+    /// Skips statement ends that were inferred from newlines, for code that continues on the next
+    /// line, like a '{' under `ritual f()`. A ';' is always kept.
+    fn skip_line_ends(&mut self) {
+        while self.check(TokenType::StatementEnd) && self.peek().lexeme != ";" {
+            self.advance();
         }
     }
 
@@ -107,40 +264,80 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             return Ok(self.advance());
         }
 
-        Self::error(self.peek(), err_msg)
+        Err(Box::new(self.error_at_next(err_msg)))
+    }
+
+    /// Consumes the token closing the delimiter `open`, pointing back at `open` if it is missing.
+    fn consume_closing(
+        &mut self,
+        token_type: TokenType,
+        open: &Token,
+        err_msg: &str,
+    ) -> Result<Token> {
+        if self.check(token_type) {
+            return Ok(self.advance());
+        }
+
+        let diagnostic = self
+            .error_at_next(err_msg)
+            .with_label(open.span, format!("To match this '{}'", open.lexeme));
+        Err(Box::new(diagnostic))
     }
 
     fn handle_primary(&mut self) -> Result<Expression> {
-        if self.match_next(&[TokenType::Identifier]) {
-            Ok(Expression::Variable(Box::new(VariableExpression {
+        let checkpoint = self.checkpoint();
+        let (expr, kind) = if self.match_next(&[TokenType::Identifier]) {
+            let expr = Expression::Variable(Box::new(VariableExpression {
                 name: self.previous().clone(),
-            })))
+            }));
+            (expr, SyntaxKind::Variable)
         } else if self.match_next(&[TokenType::False]) {
-            Ok(Expression::Literal(Box::new(LiteralExpression {
+            let expr = Expression::Literal(Box::new(LiteralExpression {
                 value: Value::Boolean(false),
-            })))
+                span: self.previous().span,
+            }));
+            (expr, SyntaxKind::Literal)
         } else if self.match_next(&[TokenType::True]) {
-            Ok(Expression::Literal(Box::new(LiteralExpression {
+            let expr = Expression::Literal(Box::new(LiteralExpression {
                 value: Value::Boolean(true),
-            })))
+                span: self.previous().span,
+            }));
+            (expr, SyntaxKind::Literal)
         } else if self.match_next(&[TokenType::None]) {
-            Ok(Expression::Literal(Box::new(LiteralExpression {
+            let expr = Expression::Literal(Box::new(LiteralExpression {
                 value: Value::None,
-            })))
+                span: self.previous().span,
+            }));
+            (expr, SyntaxKind::Literal)
         } else if self.match_next(&[TokenType::Number, TokenType::String]) {
-            Ok(Expression::Literal(Box::new(LiteralExpression {
-                value: self.previous().literal,
-            })))
+            let token = self.previous();
+            let expr = Expression::Literal(Box::new(LiteralExpression {
+                value: token.literal,
+                span: token.span,
+            }));
+            (expr, SyntaxKind::Literal)
         } else if self.match_next(&[TokenType::ParenOpen]) {
+            let open = self.previous();
             let expr = self.handle_expression()?;
-            self.consume_if(TokenType::ParenClose, "Expected ')' after expression.")?;
-            Ok(Expression::Grouping(Box::new(GroupingExpression { expr })))
+            let close = self.consume_closing(
+                TokenType::ParenClose,
+                &open,
+                "Expected ')' after expression.",
+            )?;
+            let expr = Expression::Grouping(Box::new(GroupingExpression {
+                expr,
+                span: open.span.to(close.span),
+            }));
+            (expr, SyntaxKind::Grouping)
         } else {
-            Self::error(self.peek(), "Expected an expression.")
-        }
+            return Err(Box::new(self.error_at_next("Expected an expression.")));
+        };
+        self.wrap(checkpoint, kind);
+        Ok(expr)
     }
 
     fn handle_postfix(&mut self) -> Result<Expression> {
+        let checkpoint = self.checkpoint();
         let primary = self.handle_primary()?;
         if let Expression::Variable(x) = primary {
             if self.match_next(&[TokenType::MinusMinus, TokenType::PlusPlus]) {
@@ -148,12 +345,13 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                 let op_type = match prev_token.token_type {
                     TokenType::MinusMinus => TokenType::Minus,
                     TokenType::PlusPlus => TokenType::Plus,
-                    _ => return Self::error(&prev_token, "Unknown token in postfix operator."),
+                    _ => return Self::error(prev_token.span, "Unknown token in postfix operator."),
                 };
                 let operator = Token {
                     token_type: op_type,
                     ..prev_token
                 };
+                let span = operator.span;
                 let start = Expression::Assign(Box::new(AssignExpression {
                     name: x.name.clone(),
                     value: Expression::Binary(Box::new(BinaryExpression {
@@ -161,9 +359,11 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                         operator,
                         right: Expression::Literal(Box::new(LiteralExpression {
                             value: Value::Number(1.0),
+                            span,
                         })),
                     })),
                 }));
+                self.wrap(checkpoint, SyntaxKind::Postfix);
                 return Ok(start);
             }
             return Ok(Expression::Variable(x));
@@ -172,11 +372,12 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     }
 
     fn finish_call(&mut self, callee: Expression) -> Result<Expression> {
+        let open = self.previous();
         let mut args = vec![];
         if !self.check(TokenType::ParenClose) {
             loop {
                 if args.len() > MAX_FUNC_ARG_COUNT {
-                    return Self::error(self.peek(), "Can't have more that 255 arguments.");
+                    return Self::error(self.peek().span, "Can't have more that 255 arguments.");
                 }
                 args.push(self.handle_expression()?);
                 if !self.match_next(&[TokenType::Comma]) {
@@ -184,7 +385,11 @@ impl<I: Iterator<Item = Token>> Parser<I> {
                 }
             }
         }
-        let paren = self.consume_if(TokenType::ParenClose, "Expected ')' after call arguments.")?;
+        let paren = self.consume_closing(
+            TokenType::ParenClose,
+            &open,
+            "Expected ')' after call arguments.",
+        )?;
         Ok(Expression::Call(Box::new(CallExpression {
             callee,
             args,
@@ -193,22 +398,36 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     }
 
     fn handle_call(&mut self) -> Result<Expression> {
+        let checkpoint = self.checkpoint();
         let mut expr = self.handle_postfix()?;
-        loop {
-            if self.match_next(&[TokenType::ParenOpen]) {
-                expr = self.finish_call(expr)?;
-            } else {
-                break;
+        self.chained(|parser| {
+            loop {
+                let args = parser.checkpoint();
+                if parser.match_next(&[TokenType::ParenOpen]) {
+                    parser.deepen()?;
+                    expr = parser.finish_call(expr)?;
+                    parser.wrap(args, SyntaxKind::ArgList);
+                    parser.wrap(checkpoint, SyntaxKind::Call);
+                } else if parser.match_next(&[TokenType::Dot]) {
+                    parser.deepen()?;
+                    let name = parser
+                        .consume_if(TokenType::Identifier, "Expected property name after '.'.")?;
+                    expr = Expression::Get(Box::new(GetExpression { object: expr, name }));
+                    parser.wrap(checkpoint, SyntaxKind::Get);
+                } else {
+                    break;
+                }
             }
-        }
-
-        Ok(expr)
+            Ok(expr)
+        })
     }
 
     fn handle_unary(&mut self) -> Result<Expression> {
+        let checkpoint = self.checkpoint();
         if self.match_next(&[TokenType::Not, TokenType::Minus]) {
             let operator = self.previous();
-            let right = self.handle_unary()?;
+            let right = self.nested(Self::handle_unary)?;
+            self.wrap(checkpoint, SyntaxKind::Unary);
             return Ok(Expression::Unary(Box::new(UnaryExpression {
                 operator,
                 right,
@@ -217,119 +436,129 @@ impl<I: Iterator<Item = Token>> Parser<I> {
         self.handle_call()
     }
 
+    /// Parses a left-associative chain of operators from `operators`, like `a + b + c`, as
+    /// a binary or logical expression depending on `kind`.
+    fn handle_chain(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expression>,
+        kind: SyntaxKind,
+    ) -> Result<Expression> {
+        let checkpoint = self.checkpoint();
+        let mut expr = operand(self)?;
+        self.chained(|parser| {
+            while parser.match_next(operators) {
+                let operator = parser.previous();
+                parser.deepen()?;
+                let right = operand(parser)?;
+                parser.wrap(checkpoint, kind);
+                expr = if kind == SyntaxKind::Logical {
+                    Expression::Logical(Box::new(LogicalExpression {
+                        left: expr,
+                        operator,
+                        right,
+                    }))
+                } else {
+                    Expression::Binary(Box::new(BinaryExpression {
+                        left: expr,
+                        operator,
+                        right,
+                    }))
+                };
+            }
+            Ok(expr)
+        })
+    }
+
     fn handle_factor(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_unary()?;
-        while self.match_next(&[TokenType::Divide, TokenType::Multiply]) {
-            let operator = self.previous();
-            let right = self.handle_unary()?;
-            expr = Expression::Binary(Box::new(BinaryExpression {
-                left: expr,
-                operator,
-                right,
-            }));
-        }
-        Ok(expr)
+        self.handle_chain(
+            &[TokenType::Divide, TokenType::Multiply],
+            Self::handle_unary,
+            SyntaxKind::Binary,
+        )
     }
 
     fn handle_term(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_factor()?;
-        while self.match_next(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
-            let right = self.handle_factor()?;
-            expr = Expression::Binary(Box::new(BinaryExpression {
-                left: expr,
-                operator,
-                right,
-            }));
-        }
-        Ok(expr)
+        self.handle_chain(
+            &[TokenType::Minus, TokenType::Plus],
+            Self::handle_factor,
+            SyntaxKind::Binary,
+        )
     }
 
     fn handle_comparison(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_term()?;
-        while self.match_next(&[
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
-            let operator = self.previous();
-            let right = self.handle_term()?;
-            expr = Expression::Binary(Box::new(BinaryExpression {
-                left: expr,
-                operator: operator.clone(),
-                right,
-            }));
-        }
-        Ok(expr)
+        self.handle_chain(
+            &[
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::Less,
+                TokenType::LessEqual,
+            ],
+            Self::handle_term,
+            SyntaxKind::Binary,
+        )
     }
 
     fn handle_equality(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_comparison()?;
-        while self.match_next(&[TokenType::Is, TokenType::Not]) {
-            let operator = self.previous();
-            let right = self.handle_comparison()?;
-            expr = Expression::Binary(Box::new(BinaryExpression {
-                left: expr,
-                operator: operator.clone(),
-                right,
-            }));
-        }
-        Ok(expr)
+        self.handle_chain(
+            &[TokenType::Is, TokenType::Not],
+            Self::handle_comparison,
+            SyntaxKind::Binary,
+        )
     }
 
     fn handle_and(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_equality()?;
-        while self.match_next(&[TokenType::And]) {
-            let operator = self.previous();
-            let right = self.handle_equality()?;
-            expr = Expression::Logical(Box::new(LogicalExpression {
-                left: expr,
-                operator,
-                right,
-            }))
-        }
-        Ok(expr)
+        self.handle_chain(
+            &[TokenType::And],
+            Self::handle_equality,
+            SyntaxKind::Logical,
+        )
     }
 
     fn handle_or(&mut self) -> Result<Expression> {
-        let mut expr = self.handle_and()?;
-        while self.match_next(&[TokenType::And]) {
-            let operator = self.previous();
-            let right = self.handle_and()?;
-            expr = Expression::Logical(Box::new(LogicalExpression {
-                left: expr,
-                operator,
-                right,
-            }));
-        }
-        Ok(expr)
+        self.handle_chain(&[TokenType::Or], Self::handle_and, SyntaxKind::Logical)
     }
 
     fn handle_assignment(&mut self) -> Result<Expression> {
+        let checkpoint = self.checkpoint();
         let expr = self.handle_or()?;
         if self.match_next(&[TokenType::Equal]) {
             let equals = self.previous();
-            let value = self.handle_assignment()?;
-            if let Expression::Variable(x) = expr {
-                let name = x.name;
-                return Ok(Expression::Assign(Box::new(AssignExpression {
-                    name,
-                    value,
-                })));
+            let value = self.nested(Self::handle_assignment)?;
+            self.wrap(checkpoint, SyntaxKind::Assign);
+            match expr {
+                Expression::Variable(x) => {
+                    let name = x.name;
+                    return Ok(Expression::Assign(Box::new(AssignExpression {
+                        name,
+                        value,
+                    })));
+                }
+                Expression::Get(x) => {
+                    return Ok(Expression::Set(Box::new(SetExpression {
+                        object: x.object,
+                        name: x.name,
+                        value,
+                    })));
+                }
+                _ => (),
             }
 
             // Dont throw, just report
-            Self::error::<RuntimeError>(&equals, "Invalid assignment target.").ok();
+            let diagnostic = Diagnostic::error(equals.span, "Invalid assignment target.")
+                .with_label(expr.span(), "Cannot be assigned to")
+                .with_note("Only variables and properties can be assigned to.");
+            self.errors.push(diagnostic);
         } else if self.match_next(&[TokenType::PlusEqual, TokenType::MinusEqual]) {
             let prev = self.previous();
             let token_type = match prev.token_type {
                 TokenType::PlusEqual => TokenType::Plus,
                 TokenType::MinusEqual => TokenType::Minus,
-                _ => return Self::error(&prev, "Unknown operator type in +=/-= operator."),
+                _ => return Self::error(prev.span, "Unknown operator type in +=/-= operator."),
             };
             let operator = Token { token_type, ..prev };
-            let value = self.handle_assignment()?;
+            let value = self.nested(Self::handle_assignment)?;
+            self.wrap(checkpoint, SyntaxKind::Assign);
             if let Expression::Variable(x) = expr {
                 let name = x.name;
                 return Ok(Expression::Assign(Box::new(AssignExpression {
@@ -346,16 +575,18 @@ impl<I: Iterator<Item = Token>> Parser<I> {
     }
 
     fn handle_expression(&mut self) -> Result<Expression> {
-        self.handle_assignment()
+        self.nested(Self::handle_assignment)
     }
 
     fn handle_print_statement(&mut self) -> Result<Statement> {
+        let keyword = self.previous();
         let expr = self.handle_expression()?;
         self.consume_if(
             TokenType::StatementEnd,
             "Expected statement end after expression.",
         )?;
-        Ok(Statement::Print(PrintStatement { expr }))
+        let span = keyword.span.to(expr.span());
+        Ok(Statement::Print(PrintStatement { expr, span }))
     }
 
     fn handle_expression_statement(&mut self) -> Result<Statement> {
@@ -367,103 +598,201 @@ impl<I: Iterator<Item = Token>> Parser<I> {
         Ok(Statement::Expression(ExpressionStatement { expr }))
     }
 
-    fn parse_block(&mut self) -> Result<Vec<Statement>> {
+    /// Parses the rest of a block, expecting the opening brace to have been consumed.
+    fn parse_block(&mut self) -> Result<BlockStatement> {
+        let open = self.previous();
         let mut statements = vec![];
-        while !self.check(TokenType::BraceClose) && !self.at_end() {
-            statements.push(self.handle_declaration()?);
+        self.open_blocks.push(OpenBlock::Brace);
+        self.skip_statement_ends();
+        while !self.at_block_close() && !self.at_end() {
+            if let Some(x) = self.handle_declaration() {
+                statements.push(x);
+            }
+            self.skip_statement_ends();
         }
-        self.consume_if(TokenType::BraceClose, "Expected '}' after block.")?;
-        self.consume_if(
-            TokenType::StatementEnd,
-            "Expected statement end after block.",
-        )?;
-        Ok(statements)
+        self.open_blocks.pop();
+        let close =
+            self.consume_closing(TokenType::BraceClose, &open, "Expected '}' after block.")?;
+        Ok(BlockStatement {
+            statements,
+            span: open.span.to(close.span),
+        })
+    }
+
+    /// Parses the statements of a block closed by `end`, or by `else` if `else_closes`.
+    /// The statements may start on the same line as `keyword`, but each still needs its statement
+    /// end, so a block on one line reads `if x $< 1; end`.
+    fn parse_end_block(
+        &mut self,
+        keyword: &Token,
+        else_closes: bool,
+    ) -> Result<(BlockStatement, BlockClose)> {
+        let mut statements = vec![];
+        self.open_blocks.push(if else_closes {
+            OpenBlock::EndOrElse
+        } else {
+            OpenBlock::End
+        });
+        self.skip_statement_ends();
+        let start = self.peek().span;
+        while !self.at_block_close() && !self.at_end() {
+            if let Some(x) = self.handle_declaration() {
+                statements.push(x);
+            }
+            self.skip_statement_ends();
+        }
+        self.open_blocks.pop();
+        if else_closes && self.check(TokenType::Else) {
+            let span = statements.last().map_or(start, |x| start.to(x.span()));
+            return Ok((BlockStatement { statements, span }, BlockClose::Else));
+        }
+        let close = self.consume_closing(TokenType::End, keyword, "Expected 'end' after block.")?;
+        let span = start.to(close.span);
+        Ok((BlockStatement { statements, span }, BlockClose::End))
+    }
+
+    /// Parses the block of an `if`, `else`, `while` or ritual. It is either in braces, which may
+    /// start on the next line, or a list of statements closed by `end`. Both give the same block.
+    fn handle_body(
+        &mut self,
+        keyword: &Token,
+        else_closes: bool,
+    ) -> Result<(BlockStatement, BlockClose)> {
+        self.skip_line_ends();
+        let checkpoint = self.checkpoint();
+        let block = if self.match_next(&[TokenType::BraceOpen]) {
+            (self.nested(Self::parse_block)?, BlockClose::Brace)
+        } else {
+            self.nested(|x| x.parse_end_block(keyword, else_closes))?
+        };
+        self.wrap(checkpoint, SyntaxKind::Block);
+        Ok(block)
+    }
+
+    /// Statements that end with a block end themselves, so code like `} else {` can follow on
+    /// the same line. A statement end after them is allowed, but not needed.
+    fn end_block_statement(&mut self) {
+        self.match_next(&[TokenType::StatementEnd]);
     }
 
     fn handle_block_statement(&mut self) -> Result<Statement> {
-        Ok(Statement::Block(BlockStatement {
-            statements: self.parse_block()?,
-        }))
+        let block = self.parse_block()?;
+        self.end_block_statement();
+        Ok(Statement::Block(block))
     }
 
     fn handle_if_statement(&mut self) -> Result<Statement> {
+        let keyword = self.previous();
         let condition = self.handle_expression()?;
-        let then_branch = match self.handle_statement()? {
-            Statement::Block(x) => x,
-            _ => return Self::error(&self.previous(), "Expected a block statement after if."),
-        };
+        let (then_branch, close) = self.handle_body(&keyword, true)?;
+        let mut span = keyword.span.to(then_branch.span);
         let mut else_branch = None;
-        if self.match_next(&[TokenType::Else]) {
-            else_branch = match self.handle_statement()? {
-                Statement::Block(x) => Some(x),
-                _ => {
-                    return Self::error(&self.previous(), "Expected a block statement after else.")
-                }
-            };
+        let has_else = match close {
+            // `else` may be on the next line, but not after a ';'.
+            BlockClose::Brace => {
+                self.skip_line_ends();
+                self.match_next(&[TokenType::Else])
+            }
+            BlockClose::Else => self.match_next(&[TokenType::Else]),
+            // The `end` closed the whole `if`.
+            BlockClose::End => false,
+        };
+        if has_else {
+            let else_keyword = self.previous();
+            let (block, _) = self.handle_body(&else_keyword, false)?;
+            span = span.to(block.span);
+            else_branch = Some(block);
         }
+        self.end_block_statement();
         Ok(Statement::If(IfStatement {
             condition,
             then_branch,
             else_branch,
+            span,
         }))
     }
 
     fn handle_while_statement(&mut self) -> Result<Statement> {
+        let keyword = self.previous();
         let condition = self.handle_expression()?;
-        let body = match self.handle_statement()? {
-            Statement::Block(x) => x,
-            _ => return Self::error(&self.previous(), "Expected block statement after 'while'."),
-        };
-        Ok(Statement::While(WhileStatement { condition, body }))
+        let (body, _) = self.handle_body(&keyword, false)?;
+        self.end_block_statement();
+        let span = keyword.span.to(body.span);
+        Ok(Statement::While(WhileStatement {
+            condition,
+            body,
+            span,
+        }))
     }
 
     fn handle_return_statement(&mut self) -> Result<Statement> {
         let keyword = self.previous();
-        let expr = if !self.check(TokenType::BraceClose) {
+        let expr = if !self.at_block_close() && !self.check(TokenType::StatementEnd) {
             Some(self.handle_expression()?)
         } else {
             None
         };
-        if !self.check(TokenType::BraceClose) {
-            return Self::error(
-                &keyword,
-                "Return must be the last statement in a block. (preceeding '}')",
-            );
+        self.skip_statement_ends();
+        if !self.at_block_close() {
+            let diagnostic = Diagnostic::error(
+                keyword.span,
+                "Return must be the last statement in a block. (preceeding '}' or 'end')",
+            )
+            .with_help("Remove the statements following the return.");
+            return Err(Box::new(diagnostic));
         }
         Ok(Statement::Return(ReturnStatement { expr, keyword }))
     }
 
     fn handle_statement(&mut self) -> Result<Statement> {
-        if self.match_next(&[TokenType::DollarLess]) {
-            self.handle_print_statement()
+        self.nested(Self::handle_statement_inner)
+    }
+
+    fn handle_statement_inner(&mut self) -> Result<Statement> {
+        let checkpoint = self.checkpoint();
+        let (statement, kind) = if self.match_next(&[TokenType::DollarLess]) {
+            (self.handle_print_statement()?, SyntaxKind::Print)
         } else if self.match_next(&[TokenType::BraceOpen]) {
-            self.handle_block_statement()
+            (self.handle_block_statement()?, SyntaxKind::Block)
         } else if self.match_next(&[TokenType::If]) {
-            self.handle_if_statement()
+            (self.handle_if_statement()?, SyntaxKind::If)
         } else if self.match_next(&[TokenType::While]) {
-            self.handle_while_statement()
+            (self.handle_while_statement()?, SyntaxKind::While)
         } else if self.match_next(&[TokenType::Return]) {
-            self.handle_return_statement()
+            (self.handle_return_statement()?, SyntaxKind::Return)
         } else {
-            self.handle_expression_statement()
-        }
+            let statement = self.handle_expression_statement()?;
+            (statement, SyntaxKind::ExpressionStatement)
+        };
+        self.wrap(checkpoint, kind);
+        Ok(statement)
     }
 
     fn handle_var_declaration(&mut self) -> Result<Statement> {
+        let keyword = self.previous();
         let name = self.consume_if(TokenType::Identifier, "Expected variable name.")?;
+        let mut span = keyword.span.to(name.span);
         let mut initializer = None;
         if self.match_next(&[TokenType::Equal]) {
-            initializer = Some(self.handle_expression()?);
+            let expr = self.handle_expression()?;
+            span = span.to(expr.span());
+            initializer = Some(expr);
         }
         self.consume_if(
             TokenType::StatementEnd,
             "Expected statement end after variable declaration.",
         )?;
-        Ok(Statement::Var(VarStatement { name, initializer }))
+        Ok(Statement::Var(VarStatement {
+            name,
+            initializer,
+            span,
+        }))
     }
 
     fn handle_function_declaration(&mut self, kind: FunctionKind) -> Result<Statement> {
+        let keyword = self.previous();
         let name = self.consume_if(TokenType::Identifier, &format!("Expected {} name", kind))?;
+        let param_list = self.checkpoint();
         self.consume_if(
             TokenType::ParenOpen,
             &format!("Expected '(' after {} name.", kind),
@@ -473,7 +802,7 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             loop {
                 if params.len() >= MAX_FUNC_ARG_COUNT {
                     return Self::error(
-                        self.peek(),
+                        self.peek().span,
                         &format!("Can't have more than {} parameters.", MAX_FUNC_ARG_COUNT),
                     );
                 }
@@ -484,58 +813,59 @@ impl<I: Iterator<Item = Token>> Parser<I> {
             }
         }
         self.consume_if(TokenType::ParenClose, "Expected ')' after parameters.")?;
-        self.consume_if(
-            TokenType::BraceOpen,
-            &format!("Expected '{{' before {} body.", kind),
-        )?;
-        let body = self.parse_block()?;
+        self.wrap(param_list, SyntaxKind::ParamList);
+        let (body, _) = self.handle_body(&keyword, false)?;
+        self.end_block_statement();
         Ok(Statement::Function(FunctionStatement {
-            body,
+            body: body.statements,
             name,
             params,
+            span: keyword.span.to(body.span),
         }))
     }
 
-    fn handle_declaration(&mut self) -> Result<Statement> {
-        let had_err;
-        if self.match_next(&[TokenType::Offering]) {
-            match self.handle_var_declaration() {
-                Ok(x) => return Ok(x),
-                Err(_) => had_err = true,
-            }
+    fn handle_declaration_inner(&mut self) -> Result<Statement> {
+        let checkpoint = self.checkpoint();
+        let (statement, kind) = if self.match_next(&[TokenType::Offering]) {
+            (self.handle_var_declaration()?, SyntaxKind::Var)
         } else if self.match_next(&[TokenType::Ritual]) {
-            match self.handle_function_declaration(FunctionKind::Function) {
-                Ok(x) => return Ok(x),
-                Err(_) => had_err = true,
-            }
+            let statement =
+                self.nested(|x| x.handle_function_declaration(FunctionKind::Function))?;
+            (statement, SyntaxKind::Function)
         } else {
-            match self.handle_statement() {
-                Ok(x) => return Ok(x),
-                Err(_) => had_err = true,
+            return self.handle_statement();
+        };
+        self.wrap(checkpoint, kind);
+        Ok(statement)
+    }
+
+    /// Parses a declaration, recording the error and resynchronizing if it fails.
+    fn handle_declaration(&mut self) -> Option<Statement> {
+        let checkpoint = self.checkpoint();
+        match self.handle_declaration_inner() {
+            Ok(x) => Some(x),
+            Err(err) => {
+                self.errors.push(*err);
+                self.synchronize();
+                self.wrap(checkpoint, SyntaxKind::Error);
+                None
             }
         }
-
-        //TODO: Test that his works when expected
-        if had_err {
-            self.synchronize();
-            // Dont throw error, so just return a None expr statement
-            return Ok(Statement::Expression(ExpressionStatement {
-                expr: Expression::Literal(Box::new(LiteralExpression { value: Value::None })),
-            }));
-        }
-
-        Self::error(self.peek(), "Unexpected token in declaration.")
     }
 
-    pub fn parse(&mut self) -> Vec<Statement> {
+    /// Parses all statements, returning every syntax error encountered if there were any.
+    pub fn parse(&mut self) -> std::result::Result<Vec<Statement>, Vec<Diagnostic>> {
         let mut statements = vec![];
+        self.skip_statement_ends();
         while !self.at_end() {
-            let decl_statement = match self.handle_declaration() {
-                Ok(x) => x,
-                Err(err) => panic!("Unhandled error in parser: {}", err),
-            };
-            statements.push(decl_statement);
+            if let Some(x) = self.handle_declaration() {
+                statements.push(x);
+            }
+            self.skip_statement_ends();
         }
-        statements
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(statements)
     }
 }
@@ -0,0 +1,29 @@
+use crate::interpreter::Interpreter;
+
+/// The input of an interactive session, which is run a line at a time. Every line is kept, since
+/// rituals from earlier lines can raise errors that have to point back into them.
+#[derive(Debug, Default)]
+pub struct Repl {
+    history: String,
+    strict: bool,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires every statement to end with a ';', like [`crate::Lexer::strict_terminators`].
+    pub fn strict_terminators(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Runs a line on `interpreter`, reporting its errors to the interpreter's error handler
+    /// after clearing the errors of the previous line.
+    pub fn run_line(&mut self, interpreter: &mut Interpreter, line: &str) {
+        let start = self.history.len();
+        self.history.push_str(line);
+        interpreter.run_source("<stdin>", self.history.clone(), start, self.strict);
+    }
+}
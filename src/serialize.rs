@@ -0,0 +1,452 @@
+use crate::{
+    bytecode::{Constant, Instruction, Program, Prototype},
+    span::Span,
+    value::Value,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+/// Marks the start of every compiled file.
+pub const MAGIC: &[u8; 4] = b"CAHC";
+/// The version of the compiled file format, bumped whenever the layout or the instruction set changes.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Why a compiled file couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// The file doesn't start with the expected magic bytes, so it isn't a compiled file.
+    NotCompiled,
+    UnsupportedVersion(u16),
+    /// The file is damaged or was not written by a compatible compiler.
+    Corrupt(String),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not read compiled file: {err}"),
+            Self::NotCompiled => f.write_str("Not a compiled cahlang file."),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled file has format version {version}, but only version {FORMAT_VERSION} is supported. Recompile it from source."
+            ),
+            Self::Corrupt(msg) => write!(f, "Compiled file is corrupt: {msg}."),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+fn corrupt<T>(msg: impl ToString) -> Result<T, LoadError> {
+    Err(LoadError::Corrupt(msg.to_string()))
+}
+
+/// FNV-1a, used to detect damaged files.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const TAG_NONE: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+/// Returns the opcode of an instruction and its operand, if it has one.
+fn encode_instruction(instruction: Instruction) -> (u8, Option<u32>) {
+    use Instruction::*;
+    match instruction {
+        Constant(x) => (0, Some(x)),
+        Pop => (1, None),
+        GetLocal(x) => (2, Some(x as u32)),
+        SetLocal(x) => (3, Some(x as u32)),
+        DefineLocal(x) => (4, Some(x as u32)),
+        GetGlobal(x) => (5, Some(x)),
+        SetGlobal(x) => (6, Some(x)),
+        DefineGlobal(x) => (7, Some(x)),
+        GetProperty(x) => (8, Some(x)),
+        CheckSettable(x) => (9, Some(x)),
+        SetProperty(x) => (10, Some(x)),
+        Negate => (11, None),
+        Not => (12, None),
+        Add => (13, None),
+        Subtract => (14, None),
+        Multiply => (15, None),
+        Divide => (16, None),
+        Greater => (17, None),
+        GreaterEqual => (18, None),
+        Less => (19, None),
+        LessEqual => (20, None),
+        Equal => (21, None),
+        Jump(x) => (22, Some(x)),
+        JumpIfFalse(x) => (23, Some(x)),
+        JumpIfFalseKeep(x) => (24, Some(x)),
+        JumpIfTrueKeep(x) => (25, Some(x)),
+        Loop(x) => (26, Some(x)),
+        Call(x) => (27, Some(x as u32)),
+        Print => (28, None),
+        Return => (29, None),
+        TailCall(x) => (30, Some(x as u32)),
+    }
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    /// Writes an unsigned LEB128 integer.
+    fn uint(&mut self, mut x: u64) {
+        loop {
+            let byte = (x & 0x7f) as u8;
+            x >>= 7;
+            if x == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    fn string(&mut self, x: &str) {
+        self.uint(x.len() as u64);
+        self.buf.extend_from_slice(x.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.uint(span.start as u64);
+        self.uint(span.end as u64);
+        self.uint(span.line as u64);
+        self.uint(span.column as u64);
+    }
+
+    fn constant(&mut self, constant: &Constant) -> std::io::Result<()> {
+        match constant {
+            Constant::Value(Value::None) => self.buf.push(TAG_NONE),
+            Constant::Value(Value::Boolean(x)) => self.buf.extend([TAG_BOOLEAN, *x as u8]),
+            Constant::Value(Value::Number(x)) => {
+                self.buf.push(TAG_NUMBER);
+                self.buf.extend_from_slice(&x.to_le_bytes());
+            }
+            Constant::Value(Value::String(x)) => {
+                self.buf.push(TAG_STRING);
+                self.string(x);
+            }
+            Constant::Function(x) => {
+                self.buf.push(TAG_FUNCTION);
+                self.prototype(x)?;
+            }
+            Constant::Value(x) => {
+                let msg = format!("Cannot save a {} constant.", x.type_name());
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+            }
+        }
+        Ok(())
+    }
+
+    fn prototype(&mut self, proto: &Prototype) -> std::io::Result<()> {
+        self.string(&proto.name);
+        self.uint(proto.arity as u64);
+        self.uint(proto.local_count as u64);
+        self.uint(proto.code.len() as u64);
+        for (instruction, span) in proto.code.iter().zip(&proto.spans) {
+            let (opcode, operand) = encode_instruction(*instruction);
+            self.buf.push(opcode);
+            if let Some(x) = operand {
+                self.uint(x as u64);
+            }
+            self.span(*span);
+        }
+        self.uint(proto.constants.len() as u64);
+        for constant in &proto.constants {
+            self.constant(constant)?;
+        }
+        self.uint(proto.names.len() as u64);
+        for name in &proto.names {
+            self.string(name);
+        }
+        Ok(())
+    }
+}
+
+/// Writes a compiled program in the `.cahc` format.
+pub fn write_program(program: &Program, out: &mut impl Write) -> std::io::Result<()> {
+    let mut body = Encoder { buf: vec![] };
+    body.uint(program.statements.len() as u64);
+    for proto in &program.statements {
+        body.prototype(proto)?;
+    }
+    out.write_all(MAGIC)?;
+    out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    out.write_all(&checksum(&body.buf).to_le_bytes())?;
+    out.write_all(&body.buf)
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        let byte = match self.buf.get(self.pos) {
+            Some(x) => *x,
+            None => return corrupt("unexpected end of file"),
+        };
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        if self.buf.len() - self.pos < len {
+            return corrupt("unexpected end of file");
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self) -> Result<u64, LoadError> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            x |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        corrupt("integer is too long")
+    }
+
+    fn int<T: TryFrom<u64>>(&mut self, what: &str) -> Result<T, LoadError> {
+        let x = self.uint()?;
+        T::try_from(x).or_else(|_| corrupt(format!("{what} {x} is out of range")))
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.int::<usize>("string length")?;
+        let bytes = self.bytes(len)?;
+        match std::str::from_utf8(bytes) {
+            Ok(x) => Ok(x.to_owned()),
+            Err(_) => corrupt("string is not valid UTF-8"),
+        }
+    }
+
+    fn span(&mut self) -> Result<Span, LoadError> {
+        Ok(Span::new(
+            self.int("span start")?,
+            self.int("span end")?,
+            self.int("line")?,
+            self.int("column")?,
+        ))
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, LoadError> {
+        use Instruction::*;
+        let opcode = self.byte()?;
+        let instruction = match opcode {
+            0 => Constant(self.int("operand")?),
+            1 => Pop,
+            2 => GetLocal(self.int("operand")?),
+            3 => SetLocal(self.int("operand")?),
+            4 => DefineLocal(self.int("operand")?),
+            5 => GetGlobal(self.int("operand")?),
+            6 => SetGlobal(self.int("operand")?),
+            7 => DefineGlobal(self.int("operand")?),
+            8 => GetProperty(self.int("operand")?),
+            9 => CheckSettable(self.int("operand")?),
+            10 => SetProperty(self.int("operand")?),
+            11 => Negate,
+            12 => Not,
+            13 => Add,
+            14 => Subtract,
+            15 => Multiply,
+            16 => Divide,
+            17 => Greater,
+            18 => GreaterEqual,
+            19 => Less,
+            20 => LessEqual,
+            21 => Equal,
+            22 => Jump(self.int("operand")?),
+            23 => JumpIfFalse(self.int("operand")?),
+            24 => JumpIfFalseKeep(self.int("operand")?),
+            25 => JumpIfTrueKeep(self.int("operand")?),
+            26 => Loop(self.int("operand")?),
+            27 => Call(self.int("operand")?),
+            28 => Print,
+            29 => Return,
+            30 => TailCall(self.int("operand")?),
+            x => return corrupt(format!("unknown opcode {x}")),
+        };
+        Ok(instruction)
+    }
+
+    fn constant(&mut self, depth: usize) -> Result<Constant, LoadError> {
+        let constant = match self.byte()? {
+            TAG_NONE => Constant::Value(Value::None),
+            TAG_BOOLEAN => match self.byte()? {
+                0 => Constant::Value(Value::Boolean(false)),
+                1 => Constant::Value(Value::Boolean(true)),
+                x => return corrupt(format!("invalid boolean {x}")),
+            },
+            TAG_NUMBER => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.bytes(8)?);
+                Constant::Value(Value::Number(f64::from_le_bytes(bytes)))
+            }
+            TAG_STRING => Constant::Value(Value::String(self.string()?)),
+            TAG_FUNCTION => Constant::Function(Rc::new(self.prototype(depth + 1)?)),
+            x => return corrupt(format!("unknown constant tag {x}")),
+        };
+        Ok(constant)
+    }
+
+    fn prototype(&mut self, depth: usize) -> Result<Prototype, LoadError> {
+        // Nested rituals are decoded recursively, so bound how deep they go.
+        if depth > 256 {
+            return corrupt("rituals are nested too deeply");
+        }
+        let mut proto = Prototype {
+            name: self.string()?,
+            arity: self.int("arity")?,
+            local_count: self.int("local count")?,
+            ..Default::default()
+        };
+        let code_len = self.int::<usize>("code length")?;
+        for _ in 0..code_len {
+            proto.code.push(self.instruction()?);
+            proto.spans.push(self.span()?);
+        }
+        let constant_count = self.int::<usize>("constant count")?;
+        for _ in 0..constant_count {
+            proto.constants.push(self.constant(depth)?);
+        }
+        let name_count = self.int::<usize>("name count")?;
+        for _ in 0..name_count {
+            proto.names.push(self.string()?);
+        }
+        verify(&proto).or_else(|msg| corrupt(format!("in '{}', {msg}", proto.name)))?;
+        Ok(proto)
+    }
+}
+
+/// Returns how many values an instruction needs on the stack, and how many it leaves in their place.
+fn stack_effect(instruction: Instruction) -> (usize, usize) {
+    use Instruction::*;
+    match instruction {
+        Constant(_) | GetLocal(_) | GetGlobal(_) => (0, 1),
+        Pop | DefineLocal(_) | DefineGlobal(_) | JumpIfFalse(_) | Print => (1, 0),
+        SetLocal(_) | SetGlobal(_) | GetProperty(_) | CheckSettable(_) | Negate | Not
+        | JumpIfFalseKeep(_) | JumpIfTrueKeep(_) => (1, 1),
+        SetProperty(_) | Add | Subtract | Multiply | Divide | Greater | GreaterEqual | Less
+        | LessEqual | Equal => (2, 1),
+        Jump(_) | Loop(_) => (0, 0),
+        Call(x) | TailCall(x) => (x as usize + 1, 1),
+        Return => (1, 0),
+    }
+}
+
+/// Checks that operands are in range and that the stack can't underflow, so the VM can trust the code.
+fn verify(proto: &Prototype) -> Result<(), String> {
+    use Instruction::*;
+    if proto.arity > proto.local_count {
+        return Err("it has more parameters than local slots".to_owned());
+    }
+    let len = proto.code.len();
+    // The stack height before each instruction, once it has been reached.
+    let mut heights: Vec<Option<usize>> = vec![None; len];
+    let mut pending = vec![(0, 0)];
+    while let Some((ip, height)) = pending.pop() {
+        let Some(instruction) = proto.code.get(ip).copied() else {
+            return Err(format!("execution runs past the end of the code at {ip}"));
+        };
+        match heights[ip] {
+            Some(x) if x == height => continue,
+            Some(x) => {
+                return Err(format!("stack height at {ip} is both {x} and {height}"));
+            }
+            None => heights[ip] = Some(height),
+        }
+        let in_range = match instruction {
+            Constant(x) => (x as usize) < proto.constants.len(),
+            GetLocal(x) | SetLocal(x) | DefineLocal(x) => x < proto.local_count,
+            GetGlobal(x) | SetGlobal(x) | DefineGlobal(x) | GetProperty(x) | CheckSettable(x)
+            | SetProperty(x) => (x as usize) < proto.names.len(),
+            Jump(x) | JumpIfFalse(x) | JumpIfFalseKeep(x) | JumpIfTrueKeep(x) | Loop(x) => {
+                (x as usize) < len
+            }
+            _ => true,
+        };
+        if !in_range {
+            return Err(format!(
+                "operand of {instruction:?} at {ip} is out of range"
+            ));
+        }
+        let (needs, leaves) = stack_effect(instruction);
+        if height < needs {
+            return Err(format!("{instruction:?} at {ip} underflows the stack"));
+        }
+        let next = height - needs + leaves;
+        match instruction {
+            Return => {}
+            Jump(x) | Loop(x) => pending.push((x as usize, next)),
+            JumpIfFalse(x) | JumpIfFalseKeep(x) | JumpIfTrueKeep(x) => {
+                pending.push((x as usize, next));
+                pending.push((ip + 1, next));
+            }
+            _ => pending.push((ip + 1, next)),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a program written by `write_program`, rejecting files that are damaged or from another version.
+pub fn read_program(input: &mut impl Read) -> Result<Program, LoadError> {
+    let mut buf = vec![];
+    input.read_to_end(&mut buf)?;
+    if buf.len() < MAGIC.len() || &buf[..MAGIC.len()] != MAGIC {
+        return Err(LoadError::NotCompiled);
+    }
+    let mut header = Decoder {
+        buf: &buf,
+        pos: MAGIC.len(),
+    };
+    let version = u16::from_le_bytes([header.byte()?, header.byte()?]);
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let mut expected = [0; 8];
+    expected.copy_from_slice(header.bytes(8)?);
+    let body = &buf[header.pos..];
+    if checksum(body) != u64::from_le_bytes(expected) {
+        return corrupt("checksum does not match");
+    }
+
+    let mut decoder = Decoder { buf: body, pos: 0 };
+    let count = decoder.int::<usize>("statement count")?;
+    let mut statements = vec![];
+    for _ in 0..count {
+        let proto = decoder.prototype(0)?;
+        if proto.arity != 0 {
+            return corrupt("top-level statement takes parameters");
+        }
+        statements.push(Rc::new(proto));
+    }
+    if decoder.pos != body.len() {
+        return corrupt("unexpected data after the end of the program");
+    }
+    Ok(Program { statements })
+}
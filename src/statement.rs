@@ -1,4 +1,4 @@
-use crate::{expression::Expression, token::Token};
+use crate::{expression::Expression, span::Span, token::Token};
 
 #[derive(Debug, Clone)]
 pub struct ExpressionStatement {
@@ -8,12 +8,14 @@ pub struct ExpressionStatement {
 #[derive(Debug, Clone)]
 pub struct PrintStatement {
     pub expr: Expression,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct VarStatement {
     pub name: Token,
     pub initializer: Option<Expression>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -21,11 +23,13 @@ pub struct FunctionStatement {
     pub name: Token,
     pub params: Vec<Token>,
     pub body: Vec<Statement>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct BlockStatement {
     pub statements: Vec<Statement>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -33,12 +37,14 @@ pub struct IfStatement {
     pub condition: Expression,
     pub then_branch: BlockStatement,
     pub else_branch: Option<BlockStatement>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct WhileStatement {
     pub condition: Expression,
     pub body: BlockStatement,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -58,3 +64,22 @@ pub enum Statement {
     While(WhileStatement),
     Return(ReturnStatement),
 }
+
+impl Statement {
+    /// Returns the span covering the full source range of the statement.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Expression(x) => x.expr.span(),
+            Self::Print(x) => x.span,
+            Self::Var(x) => x.span,
+            Self::Function(x) => x.span,
+            Self::Block(x) => x.span,
+            Self::If(x) => x.span,
+            Self::While(x) => x.span,
+            Self::Return(x) => match &x.expr {
+                Some(expr) => x.keyword.span.to(expr.span()),
+                None => x.keyword.span,
+            },
+        }
+    }
+}
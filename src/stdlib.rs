@@ -0,0 +1,85 @@
+use crate::{
+    error::Result,
+    interpreter::Interpreter,
+    module::NativeModule,
+    value::{NativeFunction, Value},
+};
+
+/// Math functions and constants, registered as `math`.
+pub fn math() -> NativeModule {
+    NativeModule::new("math")
+        .with_constant("pi", Value::Number(std::f64::consts::PI))
+        .with_constant("e", Value::Number(std::f64::consts::E))
+        .with_fn("sqrt", f64::sqrt)
+        .with_fn("abs", f64::abs)
+        .with_fn("floor", f64::floor)
+        .with_fn("ceil", f64::ceil)
+        .with_fn("round", f64::round)
+        .with_fn("pow", f64::powf)
+        .with_fn("min", f64::min)
+        .with_fn("max", f64::max)
+}
+
+/// Returns the byte offset of the character at `index`, or the length of the string past its end.
+fn char_offset(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(x, _)| x)
+}
+
+/// String functions, registered as `string`. Positions and lengths count characters, not bytes.
+pub fn string() -> NativeModule {
+    NativeModule::new("string")
+        .with_fn("len", |text: String| text.chars().count())
+        .with_fn("char_at", |text: String, index: usize| {
+            text.chars().nth(index).map(String::from)
+        })
+        .with_fn("slice", |text: String, start: usize, end: usize| {
+            let start = char_offset(&text, start);
+            let end = char_offset(&text, end).max(start);
+            text[start..end].to_owned()
+        })
+        .with_fn("find", |text: String, pattern: String| {
+            text.find(&pattern).map(|x| text[..x].chars().count())
+        })
+        .with_fn("chars", |text: String| {
+            text.chars().map(String::from).collect::<Vec<_>>()
+        })
+        .with_fn("upper", |text: String| text.to_uppercase())
+        .with_fn("lower", |text: String| text.to_lowercase())
+}
+
+fn write_output(interpreter: &mut Interpreter, text: &str) -> Result<Value> {
+    match interpreter.get_output().write_all(text.as_bytes()) {
+        Ok(()) => Ok(Value::None),
+        Err(err) => Err(interpreter.call_error(format!("Could not write output: {err}"))),
+    }
+}
+
+/// Reads a line from the interpreter's input without the line ending, or none at the end of input.
+fn read_line(interpreter: &mut Interpreter) -> Result<Value> {
+    let mut line = String::new();
+    match interpreter.get_input().read_line(&mut line) {
+        Ok(0) => Ok(Value::None),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(Value::String(line))
+        }
+        Err(err) => Err(interpreter.call_error(format!("Could not read input: {err}"))),
+    }
+}
+
+/// Input and output functions using the interpreter's streams, registered as `io`.
+pub fn io() -> NativeModule {
+    NativeModule::new("io")
+        .with_native(NativeFunction::new("print", 1, |interpreter, args| {
+            write_output(interpreter, &args[0].to_string())
+        }))
+        .with_native(NativeFunction::new("println", 1, |interpreter, args| {
+            write_output(interpreter, &format!("{}\n", args[0]))
+        }))
+        .with_native(NativeFunction::new("read_line", 0, |interpreter, _| {
+            read_line(interpreter)
+        }))
+}
@@ -1,4 +1,4 @@
-use crate::value::Value;
+use crate::{span::Span, value::Value};
 use std::fmt::Display;
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum TokenType {
@@ -56,24 +56,57 @@ pub enum TokenType {
 
     // Special
     StatementEnd,
+    /// Source the lexer couldn't make sense of. The message is carried as the token's literal.
+    Error,
+
+    // Trivia, only made by a lexer that preserves it
+    Whitespace,
+    /// A newline that doesn't end a statement.
+    Newline,
+    Comment,
     EOF,
 }
 
+impl TokenType {
+    /// Whether tokens of this type only preserve formatting, and mean nothing to the parser.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Self::Whitespace | Self::Newline | Self::Comment)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Value,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Value, line: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: String, literal: Value, span: Span) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
-            line,
+            span,
+        }
+    }
+
+    /// Makes an error token covering `lexeme`, explaining what is wrong with it.
+    pub fn error(lexeme: String, msg: impl ToString, span: Span) -> Self {
+        Self::new(
+            TokenType::Error,
+            lexeme,
+            Value::String(msg.to_string()),
+            span,
+        )
+    }
+
+    /// Returns the lexer's message if this is an error token.
+    pub fn error_message(&self) -> Option<&str> {
+        match (&self.token_type, &self.literal) {
+            (TokenType::Error, Value::String(x)) => Some(x),
+            _ => None,
         }
     }
 }
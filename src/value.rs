@@ -1,34 +1,40 @@
 use crate::{
-    environment::{Env, Environment},
+    bytecode::Prototype,
+    environment::Environment,
     error::Result,
-    interpreter::Interpreter,
+    interpreter::{Flow, Interpreter, TailCall},
+    module::NativeModule,
+    object::NativeObject,
     statement::FunctionStatement,
 };
-use std::fmt::{Debug, Display};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
-#[derive(Debug, Clone)]
+pub type NativeFn = Rc<dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value>>;
+
+#[derive(Clone)]
 pub struct NativeFunction {
     name: String,
     arg_count: usize,
-    func: fn(env: Env, args: Vec<Value>) -> Result<Value>,
+    func: NativeFn,
 }
 
 impl NativeFunction {
+    /// Creates a native function from a closure, which may capture host state.
     pub fn new(
         name: impl ToString,
         arg_count: usize,
-        func: fn(env: Env, args: Vec<Value>) -> Result<Value>,
+        func: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
     ) -> Self {
         Self {
             name: name.to_string(),
             arg_count,
-            func,
+            func: Rc::new(func),
         }
     }
-
-    pub fn get_name(&self) -> &str {
-        &self.name
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -48,19 +54,66 @@ impl CallableClone for Function {
     }
 }
 
+impl Function {
+    fn execute(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Flow> {
+        let mut local_env = Environment::new(Some(interpreter.get_global_env()));
+        for (param, arg) in self.declaration.params.iter().zip(args) {
+            local_env.define(param.lexeme.clone(), arg);
+        }
+        interpreter.execute_block(&self.declaration.body, local_env.into())
+    }
+
+    /// Runs a chain of tail calls in a loop instead of nesting them, so they don't grow the
+    /// native stack or count against the recursion limit.
+    #[inline(never)]
+    fn run_tail_calls(interpreter: &mut Interpreter, mut call: TailCall) -> Result<Value> {
+        interpreter.tail_call(call.span)?;
+        loop {
+            let function = call
+                .callee
+                .as_function()
+                .expect("tail called something other than a ritual");
+            let flow = function
+                .execute(interpreter, call.args)
+                .and_then(|flow| {
+                    if let Flow::TailCall(x) = &flow {
+                        interpreter.tail_call(x.span)?;
+                    }
+                    Ok(flow)
+                })
+                .map_err(|mut err| {
+                    err.push_frame(function.get_name(), call.span);
+                    err
+                })?;
+            match flow {
+                Flow::Normal => return Ok(Value::None),
+                Flow::Return(x) => return Ok(x),
+                Flow::TailCall(x) => call = x,
+            }
+        }
+    }
+}
+
 impl Callable for Function {
     fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value> {
-        let mut local_env = Environment::new(Some(interpreter.get_global_env()));
-        for (param, arg) in self.declaration.params.iter().zip(args.iter()) {
-            local_env.define(param.lexeme.clone(), arg.clone());
+        match self.execute(interpreter, args)? {
+            Flow::Normal => Ok(Value::None),
+            Flow::Return(x) => Ok(x),
+            Flow::TailCall(x) => Self::run_tail_calls(interpreter, x),
         }
-        interpreter.execute_block(&self.declaration.body, local_env.into())?;
-        Ok(Value::None)
     }
 
     fn get_arity(&self) -> usize {
         self.declaration.params.len()
     }
+
+    fn get_name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
+    fn as_function(&self) -> Option<&Function> {
+        Some(self)
+    }
 }
 
 impl CallableClone for NativeFunction {
@@ -71,12 +124,27 @@ impl CallableClone for NativeFunction {
 
 impl Callable for NativeFunction {
     fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value> {
-        (self.func)(interpreter.get_current_env(), args)
+        let value = (self.func)(interpreter, args)?;
+        interpreter.track_allocation(&value)?;
+        Ok(value)
     }
 
     fn get_arity(&self) -> usize {
         self.arg_count
     }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arg_count", &self.arg_count)
+            .finish_non_exhaustive()
+    }
 }
 
 pub trait CallableClone {
@@ -86,6 +154,16 @@ pub trait CallableClone {
 pub trait Callable: CallableClone + Debug {
     fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value>;
     fn get_arity(&self) -> usize;
+    /// Returns the name shown in tracebacks.
+    fn get_name(&self) -> &str;
+    /// Returns the bytecode of rituals compiled for the VM, which lets it call them without recursing.
+    fn get_prototype(&self) -> Option<Rc<Prototype>> {
+        None
+    }
+    /// Returns the ritual if this is one run by the tree-walker, which lets it run tail calls without recursing.
+    fn as_function(&self) -> Option<&Function> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -94,16 +172,57 @@ pub enum Value {
     Number(f64),
     Boolean(bool),
     Callable(Box<dyn Callable>),
+    List(Vec<Value>),
+    Map(HashMap<String, Value>),
+    Native(NativeObject),
+    Module(Rc<NativeModule>),
     None,
 }
 
+impl Value {
+    /// Returns the name of the type of the value, as shown to scripts.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Self::String(_) => "string",
+            Self::Number(_) => "number",
+            Self::Boolean(_) => "boolean",
+            Self::Callable(_) => "function",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Native(x) => x.get_type_name(),
+            Self::Module(_) => "module",
+            Self::None => "none",
+        }
+    }
+
+    /// Approximates how many bytes the value owns on the heap, counting strings and collections.
+    pub(crate) fn heap_size(&self) -> usize {
+        match self {
+            Self::String(x) => x.len(),
+            Self::List(x) => {
+                x.len() * std::mem::size_of::<Value>()
+                    + x.iter().map(Value::heap_size).sum::<usize>()
+            }
+            Self::Map(x) => x
+                .iter()
+                .map(|(key, val)| key.len() + std::mem::size_of::<Value>() + val.heap_size())
+                .sum(),
+            _ => 0,
+        }
+    }
+}
+
 impl Clone for Value {
     fn clone(&self) -> Self {
         match self {
             Self::String(x) => Self::String(x.clone()),
-            Self::Number(x) => Self::Number(x.clone()),
-            Self::Boolean(x) => Self::Boolean(x.clone()),
+            Self::Number(x) => Self::Number(*x),
+            Self::Boolean(x) => Self::Boolean(*x),
             Self::Callable(x) => Self::Callable(x.clone_box()),
+            Self::List(x) => Self::List(x.clone()),
+            Self::Map(x) => Self::Map(x.clone()),
+            Self::Native(x) => Self::Native(x.clone()),
+            Self::Module(x) => Self::Module(x.clone()),
             Self::None => Self::None,
         }
     }
@@ -116,6 +235,31 @@ impl Display for Value {
             Value::Number(x) => f.write_fmt(format_args!("{x}")),
             Value::Boolean(x) => f.write_fmt(format_args!("{x}")),
             Value::Callable(_) => f.write_str("<function>"),
+            Value::List(x) => {
+                f.write_str("[")?;
+                for (i, item) in x.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_fmt(format_args!("{item}"))?;
+                }
+                f.write_str("]")
+            }
+            Value::Map(x) => {
+                // Sort the keys so the output doesn't depend on the hash order.
+                let mut keys = x.keys().collect::<Vec<_>>();
+                keys.sort();
+                f.write_str("{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_fmt(format_args!("{key}: {}", x[key]))?;
+                }
+                f.write_str("}")
+            }
+            Value::Native(x) => f.write_fmt(format_args!("<{}>", x.get_type_name())),
+            Value::Module(x) => f.write_fmt(format_args!("<module {}>", x.get_name())),
             Value::None => f.write_str("none"),
         }
     }
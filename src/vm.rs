@@ -0,0 +1,343 @@
+use crate::{
+    bytecode::{Constant, Instruction, Prototype},
+    error::{Result, RuntimeError},
+    interpreter::Interpreter,
+    span::Span,
+    token::{Token, TokenType},
+    value::{Callable, CallableClone, Value},
+};
+use std::rc::Rc;
+
+/// A ritual compiled to bytecode.
+#[derive(Debug, Clone)]
+pub struct CompiledFunction {
+    proto: Rc<Prototype>,
+}
+
+impl CompiledFunction {
+    pub fn new(proto: Rc<Prototype>) -> Self {
+        Self { proto }
+    }
+}
+
+impl CallableClone for CompiledFunction {
+    fn clone_box(&self) -> Box<dyn Callable> {
+        Box::new(self.clone())
+    }
+}
+
+impl Callable for CompiledFunction {
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value> {
+        run(interpreter, self.proto.clone(), args)
+    }
+
+    fn get_arity(&self) -> usize {
+        self.proto.arity as usize
+    }
+
+    fn get_name(&self) -> &str {
+        &self.proto.name
+    }
+
+    fn get_prototype(&self) -> Option<Rc<Prototype>> {
+        Some(self.proto.clone())
+    }
+}
+
+/// A ritual being run by the VM.
+struct Frame {
+    proto: Rc<Prototype>,
+    ip: usize,
+    locals: Vec<Value>,
+    /// Where the frame's part of the operand stack starts.
+    stack_base: usize,
+    /// The call that created the frame, and the span of the call it was made from.
+    /// The outermost frame has none, since whoever started the VM did the bookkeeping.
+    call: Option<(Span, Option<Span>)>,
+    /// Where the ritual was tail called, and the ritual the frame was originally created for.
+    tail: Option<(Span, Rc<Prototype>)>,
+}
+
+impl Frame {
+    fn new(
+        proto: Rc<Prototype>,
+        mut args: Vec<Value>,
+        stack_base: usize,
+        call: Option<(Span, Option<Span>)>,
+    ) -> Self {
+        args.resize(proto.local_count as usize, Value::None);
+        Self {
+            proto,
+            ip: 0,
+            locals: args,
+            stack_base,
+            call,
+            tail: None,
+        }
+    }
+
+    /// Makes a token for a name, so lookups can share the error messages of the tree-walking backend.
+    fn name_token(&self, index: u32, span: Span) -> Token {
+        let name = self.proto.names[index as usize].clone();
+        Token::new(TokenType::Identifier, name, Value::None, span)
+    }
+}
+
+struct Vm {
+    frame: Frame,
+    /// The frames of the callers of the current frame.
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+}
+
+impl Vm {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("popped an empty VM stack")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("peeked an empty VM stack")
+    }
+
+    fn binary(
+        &mut self,
+        interpreter: &mut Interpreter,
+        operator: TokenType,
+        span: Span,
+    ) -> Result<()> {
+        let right = self.pop();
+        let left = self.pop();
+        let value = interpreter.binary_op(operator, left, right, span)?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn call(&mut self, interpreter: &mut Interpreter, count: u16, span: Span) -> Result<()> {
+        let args = self.stack.split_off(self.stack.len() - count as usize);
+        let callee = self.pop();
+        let callable = Interpreter::check_callable(callee, args.len(), span)?;
+        match callable.get_prototype() {
+            Some(proto) => {
+                let previous_span = interpreter.enter_call(span)?;
+                let frame = Frame::new(proto, args, self.stack.len(), Some((span, previous_span)));
+                let caller = std::mem::replace(&mut self.frame, frame);
+                self.frames.push(caller);
+            }
+            None => {
+                let value = interpreter.call_value(Value::Callable(callable), args, span)?;
+                self.stack.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the current frame with the called ritual, so chains of tail calls run in constant space.
+    /// At the top level, or for callables that aren't compiled rituals, this is an ordinary call.
+    fn tail_call(&mut self, interpreter: &mut Interpreter, count: u16, span: Span) -> Result<()> {
+        if !interpreter.in_call() {
+            return self.call(interpreter, count, span);
+        }
+        let args = self.stack.split_off(self.stack.len() - count as usize);
+        let callee = self.pop();
+        let callable = Interpreter::check_callable(callee, args.len(), span)?;
+        let Some(proto) = callable.get_prototype() else {
+            let value = interpreter.call_value(Value::Callable(callable), args, span)?;
+            self.stack.push(value);
+            return Ok(());
+        };
+        interpreter.tail_call(span)?;
+        let stack_base = self.frame.stack_base;
+        self.stack.truncate(stack_base);
+        let original = match self.frame.tail.take() {
+            Some((_, x)) => x,
+            None => self.frame.proto.clone(),
+        };
+        let mut frame = Frame::new(proto, args, stack_base, self.frame.call.take());
+        frame.tail = Some((span, original));
+        self.frame = frame;
+        Ok(())
+    }
+
+    /// Returns from the current frame, or gives back the value if it was the outermost one.
+    fn ret(&mut self, interpreter: &mut Interpreter) -> Option<Value> {
+        let value = self.pop();
+        let Some(caller) = self.frames.pop() else {
+            return Some(value);
+        };
+        let frame = std::mem::replace(&mut self.frame, caller);
+        self.stack.truncate(frame.stack_base);
+        if let Some((_, previous_span)) = frame.call {
+            interpreter.exit_call(previous_span);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    /// Leaves every frame after an error, adding them to its traceback like nested calls would.
+    /// A frame that was replaced by tail calls shows up as the last ritual called, followed by the original one.
+    fn unwind(&mut self, interpreter: &mut Interpreter, mut err: RuntimeError) -> RuntimeError {
+        loop {
+            let frame = &self.frame;
+            let mut name = &frame.proto.name;
+            if let Some((span, original)) = &frame.tail {
+                err.push_frame(name, *span);
+                name = &original.name;
+            }
+            if let Some((call_span, previous_span)) = frame.call {
+                interpreter.exit_call(previous_span);
+                err.push_frame(name, call_span);
+            }
+            let Some(caller) = self.frames.pop() else {
+                return err;
+            };
+            self.frame = caller;
+        }
+    }
+
+    fn execute(&mut self, interpreter: &mut Interpreter) -> Result<Value> {
+        loop {
+            let frame = &mut self.frame;
+            let instruction = frame.proto.code[frame.ip];
+            let span = frame.proto.spans[frame.ip];
+            frame.ip += 1;
+            interpreter.tick(|| span)?;
+            match instruction {
+                Instruction::Constant(index) => {
+                    let value = match &self.frame.proto.constants[index as usize] {
+                        Constant::Value(x) => x.clone(),
+                        Constant::Function(x) => {
+                            Value::Callable(Box::new(CompiledFunction::new(x.clone())))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::GetLocal(slot) => {
+                    let value = self.frame.locals[slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Instruction::SetLocal(slot) => {
+                    self.frame.locals[slot as usize] = self.peek().clone();
+                }
+                Instruction::DefineLocal(slot) => {
+                    self.frame.locals[slot as usize] = self.pop();
+                }
+                Instruction::GetGlobal(index) => {
+                    let name = self.frame.name_token(index, span);
+                    let value = interpreter.get_global_env().borrow().get(&name)?;
+                    self.stack.push(value);
+                }
+                Instruction::SetGlobal(index) => {
+                    let name = self.frame.name_token(index, span);
+                    let value = self.peek().clone();
+                    interpreter
+                        .get_global_env()
+                        .borrow_mut()
+                        .assign(&name, value)?;
+                }
+                Instruction::DefineGlobal(index) => {
+                    let name = self.frame.proto.names[index as usize].clone();
+                    let value = self.pop();
+                    interpreter.define_global(name, value);
+                }
+                Instruction::GetProperty(index) => {
+                    let name = self.frame.name_token(index, span);
+                    let value = match self.pop() {
+                        Value::Native(x) => x.get(interpreter, &name)?,
+                        Value::Module(x) => x.get(&name)?,
+                        x => {
+                            let msg = format!("Cannot access property on {}.", x.type_name());
+                            return Interpreter::error(span, msg);
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::CheckSettable(_) => {
+                    let object = self.peek();
+                    if !matches!(object, Value::Native(_)) {
+                        let msg = format!("Cannot set property on {}.", object.type_name());
+                        return Interpreter::error(span, msg);
+                    }
+                }
+                Instruction::SetProperty(index) => {
+                    let name = self.frame.name_token(index, span);
+                    let value = self.pop();
+                    if let Value::Native(object) = self.pop() {
+                        object.set(interpreter, &name, value.clone())?;
+                    }
+                    self.stack.push(value);
+                }
+                Instruction::Negate => {
+                    let value = Interpreter::unary_op(TokenType::Minus, self.pop(), span)?;
+                    self.stack.push(value);
+                }
+                Instruction::Not => {
+                    let value = Interpreter::unary_op(TokenType::Not, self.pop(), span)?;
+                    self.stack.push(value);
+                }
+                Instruction::Add => self.binary(interpreter, TokenType::Plus, span)?,
+                Instruction::Subtract => self.binary(interpreter, TokenType::Minus, span)?,
+                Instruction::Multiply => self.binary(interpreter, TokenType::Multiply, span)?,
+                Instruction::Divide => self.binary(interpreter, TokenType::Divide, span)?,
+                Instruction::Greater => self.binary(interpreter, TokenType::Greater, span)?,
+                Instruction::GreaterEqual => {
+                    self.binary(interpreter, TokenType::GreaterEqual, span)?
+                }
+                Instruction::Less => self.binary(interpreter, TokenType::Less, span)?,
+                Instruction::LessEqual => self.binary(interpreter, TokenType::LessEqual, span)?,
+                Instruction::Equal => self.binary(interpreter, TokenType::Is, span)?,
+                Instruction::Jump(target) => self.frame.ip = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if !Interpreter::is_truthy(&self.pop()) {
+                        self.frame.ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfFalseKeep(target) => {
+                    if !Interpreter::is_truthy(self.peek()) {
+                        self.frame.ip = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrueKeep(target) => {
+                    if Interpreter::is_truthy(self.peek()) {
+                        self.frame.ip = target as usize;
+                    }
+                }
+                Instruction::Loop(target) => {
+                    interpreter.check_cancelled(span)?;
+                    self.frame.ip = target as usize;
+                }
+                Instruction::Call(count) => self.call(interpreter, count, span)?,
+                Instruction::TailCall(count) => self.tail_call(interpreter, count, span)?,
+                Instruction::Print => {
+                    let value = self.pop();
+                    if let Err(err) = writeln!(interpreter.get_output(), "{}", value) {
+                        return Interpreter::error(span, format!("Could not write output: {err}"));
+                    }
+                }
+                Instruction::Return => {
+                    if let Some(value) = self.ret(interpreter) {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs a prototype with the given arguments in its first local slots, returning what it returns.
+/// Calls between compiled rituals are handled inside the VM, so they don't use up the native stack.
+pub(crate) fn run(
+    interpreter: &mut Interpreter,
+    proto: Rc<Prototype>,
+    args: Vec<Value>,
+) -> Result<Value> {
+    let mut vm = Vm {
+        frame: Frame::new(proto, args, 0, None),
+        frames: vec![],
+        stack: vec![],
+    };
+    vm.execute(interpreter)
+        .map_err(|err| vm.unwind(interpreter, err))
+}
@@ -0,0 +1,169 @@
+//! Checks that each option of `InterpreterBuilder` has an effect on the interpreter it builds.
+
+mod common;
+
+use cahlang_ast::{
+    Backend, CancellationToken, CollectingErrorHandler, ErrorKind, Interpreter, InterpreterBuilder,
+    NativeModule, OutputBuffer, RuntimeError, SharedErrorHandler, Value,
+};
+use common::{parse, Harness};
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+/// What a script printed, the runtime errors it reported and the error that aborted it, if any.
+struct Run {
+    output: String,
+    errors: Vec<String>,
+    fatal: Option<RuntimeError>,
+}
+
+/// Builds an interpreter from `builder`, with its output and errors captured, and runs `source`.
+fn run(builder: InterpreterBuilder, source: &str) -> Run {
+    let mut harness = Harness::new(builder);
+    let fatal = harness.interpret(source).err();
+    Run {
+        output: harness.output(),
+        errors: harness.errors(),
+        fatal,
+    }
+}
+
+#[test]
+fn backend() {
+    for (backend, compiled) in [(Backend::TreeWalker, false), (Backend::Bytecode, true)] {
+        let mut interpreter = Interpreter::builder()
+            .output(OutputBuffer::new())
+            .backend(backend)
+            .build();
+        interpreter.interpret(parse("ritual f() {\n}\n")).unwrap();
+        assert_eq!(interpreter.get_config().backend, backend);
+        // Rituals are compiled to bytecode only for the VM.
+        let Some(Value::Callable(f)) = interpreter.get_global("f") else {
+            panic!("expected a ritual");
+        };
+        assert_eq!(f.get_prototype().is_some(), compiled);
+        assert_eq!(f.as_function().is_some(), !compiled);
+    }
+}
+
+#[test]
+fn optimize() {
+    // Folded into a single constant, the sum fits in a budget that evaluating it doesn't.
+    let source = "$< 1 + 2 + 3 + 4 + 5 + 6 + 7 + 8\n";
+    let budget = || Interpreter::builder().instruction_budget(5);
+    let plain = run(budget(), source);
+    assert_eq!(
+        plain.fatal.map(|x| x.get_kind()),
+        Some(ErrorKind::InstructionLimit)
+    );
+    let optimized = run(budget().optimize(true), source);
+    assert!(optimized.fatal.is_none());
+    assert_eq!(optimized.output, "36\n");
+}
+
+#[test]
+fn limits() {
+    let recursive = "ritual f(n) {\n    if n < 1 {\n        return 0\n    }\n    return 1 + f(n - 1)\n}\n$< f(10)\n";
+    let run_limited = |builder: InterpreterBuilder, source: &str| {
+        run(builder, source).fatal.map(|x| x.get_kind())
+    };
+    assert_eq!(run(Interpreter::builder(), recursive).output, "10\n");
+    let shallow = run(Interpreter::builder().recursion_limit(5), recursive);
+    assert_eq!(
+        shallow.errors,
+        ["Stack overflow, calls were nested deeper than 5."]
+    );
+
+    let endless = "while true {\n}\n";
+    assert_eq!(
+        run_limited(Interpreter::builder().instruction_budget(100), endless),
+        Some(ErrorKind::InstructionLimit)
+    );
+    assert_eq!(
+        run_limited(
+            Interpreter::builder().time_limit(Duration::from_millis(10)),
+            endless
+        ),
+        Some(ErrorKind::TimeLimit)
+    );
+    let growing = "offering s = \"\"\nwhile true {\n    s = s + \"more\"\n}\n";
+    assert_eq!(
+        run_limited(Interpreter::builder().allocation_limit(1000), growing),
+        Some(ErrorKind::AllocationLimit)
+    );
+
+    let token = CancellationToken::new();
+    let builder = Interpreter::builder()
+        .cancellation_token(token.clone())
+        .function("stop", move || token.cancel());
+    assert_eq!(
+        run_limited(builder, "stop()\nwhile true {\n}\n"),
+        Some(ErrorKind::Cancelled)
+    );
+}
+
+#[test]
+fn strict_concat() {
+    let source = "$< \"n = \" + 1\n";
+    assert_eq!(run(Interpreter::builder(), source).output, "n = 1\n");
+    let strict = run(Interpreter::builder().strict_concat(true), source);
+    assert_eq!(strict.output, "");
+    assert_eq!(strict.errors, ["Cannot add number to string."]);
+}
+
+#[test]
+fn stdlib() {
+    let source = "$< math.sqrt(16)\n$< string.upper(\"a\")\nio.println(\"b\")\n";
+    let without = run(Interpreter::builder(), source);
+    assert_eq!(without.output, "");
+    assert_eq!(
+        without.errors,
+        [
+            "Undefined variable 'math'",
+            "Undefined variable 'string'",
+            "Undefined variable 'io'",
+        ]
+    );
+    let with = run(Interpreter::builder().stdlib(), source);
+    assert_eq!(with.output, "4\nA\nb\n");
+    assert!(with.errors.is_empty());
+}
+
+#[test]
+fn natives_modules_and_globals() {
+    let builder = Interpreter::builder()
+        .module(NativeModule::new("shapes").with_constant("sides", Value::Number(4.)))
+        .function("twice", |x: f64| x * 2.)
+        .global("name", Value::String("square".to_owned()));
+    let result = run(builder, "$< name + \" \" + twice(shapes.sides)\n");
+    assert_eq!(result.output, "square 8\n");
+}
+
+#[test]
+fn input() {
+    let builder = Interpreter::builder()
+        .stdlib()
+        .input(std::io::Cursor::new("first\nsecond"));
+    let result = run(
+        builder,
+        "$< io.read_line()\n$< io.read_line()\n$< io.read_line()\n",
+    );
+    assert_eq!(result.output, "first\nsecond\nnone\n");
+}
+
+#[test]
+fn error_handler() {
+    let collecting = Rc::new(RefCell::new(CollectingErrorHandler::new()));
+    let shared: SharedErrorHandler = collecting.clone();
+    let mut interpreter = Interpreter::builder()
+        .output(OutputBuffer::new())
+        .error_handler(shared.clone())
+        .build();
+    assert!(Rc::ptr_eq(&interpreter.get_error_handler(), &shared));
+    interpreter.interpret(parse("$< missing\n")).unwrap();
+    let errors = collecting.borrow();
+    assert_eq!(errors.runtime_errors().len(), 1);
+    assert_eq!(
+        errors.runtime_errors()[0].to_string(),
+        "Undefined variable 'missing'"
+    );
+}
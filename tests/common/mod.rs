@@ -0,0 +1,69 @@
+//! Runs scripts for the integration tests, with their output and reported errors captured.
+
+// Each test crate uses only some of these.
+#![allow(dead_code)]
+
+use cahlang_ast::{
+    error::Result, statement::Statement, CollectingErrorHandler, Interpreter, InterpreterBuilder,
+    Lexer, OutputBuffer, Parser, RuntimeError,
+};
+use std::{cell::RefCell, rc::Rc};
+
+pub fn parse(source: &str) -> Vec<Statement> {
+    Parser::new(Lexer::new(source.to_owned()))
+        .parse()
+        .expect("test script should parse")
+}
+
+/// An interpreter whose output and reported errors are kept for the test to look at.
+pub struct Harness {
+    pub interpreter: Interpreter,
+    pub output: OutputBuffer,
+    err_handler: Rc<RefCell<CollectingErrorHandler>>,
+}
+
+impl Harness {
+    /// Builds the interpreter from `builder`, replacing its output and error handler.
+    pub fn new(builder: InterpreterBuilder) -> Self {
+        let output = OutputBuffer::new();
+        let err_handler = Rc::new(RefCell::new(CollectingErrorHandler::new()));
+        let interpreter = builder
+            .error_handler(err_handler.clone())
+            .output(output.clone())
+            .build();
+        Self {
+            interpreter,
+            output,
+            err_handler,
+        }
+    }
+
+    /// Runs a script, returning the error that aborted it, if any.
+    pub fn interpret(&mut self, source: &str) -> Result<()> {
+        self.interpreter.interpret(parse(source))
+    }
+
+    pub fn output(&self) -> String {
+        self.output.contents()
+    }
+
+    /// Formats the runtime errors reported so far with `format`.
+    pub fn map_errors(&self, format: impl Fn(&RuntimeError) -> String) -> Vec<String> {
+        self.err_handler
+            .borrow()
+            .runtime_errors()
+            .iter()
+            .map(format)
+            .collect()
+    }
+
+    /// The messages of the runtime errors reported so far.
+    pub fn errors(&self) -> Vec<String> {
+        self.map_errors(ToString::to_string)
+    }
+
+    /// The runtime errors reported so far, each prefixed with where it happened.
+    pub fn located_errors(&self) -> Vec<String> {
+        self.map_errors(|err| format!("{}: {err}", err.get_span()))
+    }
+}
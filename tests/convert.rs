@@ -0,0 +1,147 @@
+//! Checks the conversions between script values and Rust values, and functions registered with them.
+
+mod common;
+
+use cahlang_ast::{FromValue, Interpreter, IntoValue, Value};
+use common::Harness;
+use std::collections::HashMap;
+
+fn number<T: FromValue>(x: f64) -> Option<T> {
+    T::from_value(Value::Number(x)).ok()
+}
+
+#[test]
+fn integers_must_be_whole_and_in_range() {
+    assert_eq!(number::<u8>(255.), Some(255));
+    assert_eq!(number::<u8>(256.), None);
+    assert_eq!(number::<u8>(-1.), None);
+    assert_eq!(number::<i8>(-128.), Some(-128));
+    assert_eq!(number::<i8>(128.), None);
+    assert_eq!(number::<i32>(1.5), None);
+    assert_eq!(number::<i32>(f64::NAN), None);
+    assert_eq!(number::<usize>(f64::INFINITY), None);
+    // 2^63 and 2^64 are one past the largest i64 and u64, and must not saturate to them.
+    let two_pow_63 = 2f64.powi(63);
+    assert_eq!(number::<i64>(two_pow_63), None);
+    assert_eq!(number::<i64>(-two_pow_63), Some(i64::MIN));
+    assert_eq!(number::<u64>(two_pow_63), Some(1 << 63));
+    assert_eq!(number::<u64>(2f64.powi(64)), None);
+    assert_eq!(7u16.into_value().to_string(), "7");
+}
+
+#[test]
+fn values_convert_both_ways() {
+    assert_eq!(String::from_value("hi".into_value()).unwrap(), "hi");
+    assert!(bool::from_value(true.into_value()).unwrap());
+    assert_eq!(f64::from_value(0.5.into_value()).unwrap(), 0.5);
+    assert_eq!(Option::<f64>::from_value(Value::None).unwrap(), None);
+    assert_eq!(
+        Option::<f64>::from_value(Value::Number(1.)).unwrap(),
+        Some(1.)
+    );
+    assert!(matches!(().into_value(), Value::None));
+    assert!(matches!(None::<bool>.into_value(), Value::None));
+
+    let list = vec![1, 2, 3].into_value();
+    assert_eq!(list.to_string(), "[1, 2, 3]");
+    assert_eq!(Vec::<u32>::from_value(list).unwrap(), [1, 2, 3]);
+    let map = HashMap::from([("b".to_owned(), "x"), ("a".to_owned(), "y")]).into_value();
+    assert_eq!(map.to_string(), "{a: y, b: x}");
+    let map = HashMap::<String, String>::from_value(map).unwrap();
+    assert_eq!(map["a"], "y");
+
+    let err = Vec::<f64>::from_value(Value::List(vec![Value::Boolean(true)])).unwrap_err();
+    assert_eq!(
+        (err.expected.as_str(), err.found.as_str()),
+        ("number", "boolean")
+    );
+    let err = String::from_value(Value::Number(1.)).unwrap_err();
+    assert_eq!(
+        (err.expected.as_str(), err.found.as_str()),
+        ("string", "number")
+    );
+}
+
+/// Runs a script with some natives, returning what it printed and the runtime errors.
+fn run(source: &str, setup: impl Fn(&Interpreter)) -> (String, Vec<String>) {
+    let mut harness = Harness::new(Interpreter::builder());
+    setup(&harness.interpreter);
+    harness.interpret(source).unwrap();
+    (harness.output(), harness.errors())
+}
+
+#[test]
+fn functions_of_every_arity() {
+    let (output, errors) = run(
+        r#"
+$< zero()
+$< one(1)
+$< two(1, 2)
+$< three(1, 2, 3)
+$< four(1, 2, 3, 4)
+$< five(1, 2, 3, 4, 5)
+$< six(1, 2, 3, 4, 5, 6)
+"#,
+        |x| {
+            x.register_fn("zero", || 0);
+            x.register_fn("one", |a: i32| a);
+            x.register_fn("two", |a: i32, b: i32| a + b);
+            x.register_fn("three", |a: i32, b: i32, c: i32| a + b + c);
+            x.register_fn("four", |a: i32, b: i32, c: i32, d: i32| a + b + c + d);
+            x.register_fn("five", |a: i32, b: i32, c: i32, d: i32, e: i32| {
+                a + b + c + d + e
+            });
+            x.register_fn("six", |a: i32, b: i32, c: i32, d: i32, e: i32, f: i32| {
+                a + b + c + d + e + f
+            });
+        },
+    );
+    assert_eq!(output, "0\n1\n3\n6\n10\n15\n21\n");
+    assert!(errors.is_empty(), "{errors:?}");
+}
+
+#[test]
+fn arguments_are_checked() {
+    let (output, errors) = run(
+        "$< half(3)\n$< half(\"3\")\n$< half(1, 2)\n$< repeat(\"ab\", 2.5)\n",
+        |x| {
+            x.register_fn("half", |a: f64| a / 2.);
+            x.register_fn("repeat", |text: String, times: usize| text.repeat(times));
+        },
+    );
+    assert_eq!(output, "1.5\n");
+    assert_eq!(
+        errors,
+        [
+            "Expected argument 1 to 'half' to be number, but got string",
+            "Exptected 1 arguments, but got 2",
+            "Expected argument 2 to 'repeat' to be integer (usize), but got number",
+        ]
+    );
+}
+
+#[test]
+fn str_arguments_borrow_the_string() {
+    let (output, errors) = run("$< shout(\"hey\", 2)\n$< shout(1, 2)\n", |x| {
+        x.register_fn("shout", |text: &str, times: usize| {
+            text.to_uppercase().repeat(times)
+        });
+    });
+    assert_eq!(output, "HEYHEY\n");
+    assert_eq!(
+        errors,
+        ["Expected argument 1 to 'shout' to be string, but got number"]
+    );
+}
+
+#[test]
+fn functions_returning_errors_raise_them() {
+    let (output, errors) = run(
+        "$< parse(\"12\")\n$< parse(\"twelve\")\n$< \"after\"\n",
+        |x| {
+            x.register_fn("parse", |text: &str| text.parse::<f64>());
+        },
+    );
+    assert_eq!(output, "12\nafter\n");
+    assert_eq!(errors, ["invalid float literal"]);
+}
@@ -0,0 +1,162 @@
+//! Checks that syntax trees give back their source exactly, and that the typed views find the
+//! parts of each construct.
+
+use cahlang_ast::{
+    cst::{ExpressionNode, StatementNode, SyntaxKind},
+    token::TokenType,
+    Lexer, Parser, SyntaxTree,
+};
+
+const SCRIPT: &str = r#"? Greets a few times.
+ritual greet(name,  times) {
+    offering i = 0   ? counter
+    while i < times {
+        $< "héllo " + name
+
+
+        i += 1
+    }
+    return (i)
+}
+
+if greet("wörld", 2) is 2 {
+    $< not false and none or -1
+}
+else {
+    $< (counter++)
+}
+object.field = call(1, 2)(3).other
+"#;
+
+fn assert_round_trip(source: &str) -> SyntaxTree {
+    let tree = SyntaxTree::parse(source);
+    assert_eq!(tree.to_string(), source);
+    tree
+}
+
+#[test]
+fn round_trips_byte_for_byte() {
+    assert_round_trip(SCRIPT);
+    assert_round_trip("");
+    assert_round_trip("\n\n  \t\n");
+    assert_round_trip("? only a comment");
+    assert_round_trip("$< 1\r\n$< 2\r\n");
+    assert_round_trip("offering x = (1 +\n    2)\n$< x");
+    assert_round_trip("  $< [ 1 ]  ? trailing  \n\n");
+    let tree = assert_round_trip("if x\n    $< 1 ? one\nelse\n    $< 2\nend\n");
+    let Some(StatementNode::If(branch)) = tree.script().statements().next() else {
+        panic!("expected an if");
+    };
+    assert_eq!(branch.then_branch().unwrap().statements().count(), 1);
+    assert_eq!(branch.else_branch().unwrap().syntax().text(), "$< 2\nend");
+    let tree = assert_round_trip(SCRIPT);
+    assert!(tree.errors().is_empty());
+}
+
+#[test]
+fn round_trips_broken_code() {
+    for source in [
+        "offering = 1\n$< ok",
+        "$< 1 @ 2\n$< \"open",
+        "ritual f( {\n$< 1\n}\n",
+        "if x {\n  $< 1\n",
+        "$< ) ) $ €\nx = = 2\n",
+        // Closers that belong to no open block.
+        "{\n  end\n}\n$< 1",
+        "{ else }",
+        "if true\n  }\nend",
+        "ritual f(n)\n  if n < 1 { return 0 }\n  return f(n - 1)\n}\n$< f(10)",
+    ] {
+        let tree = assert_round_trip(source);
+        assert!(!tree.errors().is_empty(), "{source}");
+    }
+    let tree = SyntaxTree::parse("offering = 1\n$< ok\n");
+    let errors: Vec<_> = tree.script().errors().collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].text(), "offering = 1\n");
+    assert_eq!(tree.script().statements().count(), 1);
+}
+
+#[test]
+fn trivia_lexer_keeps_every_byte() {
+    let mut lexer = Lexer::with_trivia(SCRIPT.to_owned());
+    let mut text = String::new();
+    let mut types = vec![];
+    loop {
+        let token = lexer.lex();
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+        text.push_str(&token.lexeme);
+        types.push(token.token_type);
+    }
+    assert_eq!(text, SCRIPT);
+    for trivia in [
+        TokenType::Whitespace,
+        TokenType::Newline,
+        TokenType::Comment,
+    ] {
+        assert!(types.contains(&trivia));
+    }
+
+    // The parser skips trivia, so both lexers give the same statements.
+    let plain = Parser::new(Lexer::new(SCRIPT.to_owned())).parse().unwrap();
+    let trivia = Parser::new(Lexer::with_trivia(SCRIPT.to_owned()))
+        .parse()
+        .unwrap();
+    assert_eq!(format!("{plain:?}"), format!("{trivia:?}"));
+}
+
+#[test]
+fn typed_views() {
+    let tree = SyntaxTree::parse(SCRIPT);
+    let statements: Vec<_> = tree.script().statements().collect();
+    assert_eq!(statements.len(), 3);
+
+    let StatementNode::Function(greet) = statements[0] else {
+        panic!("expected a ritual");
+    };
+    assert_eq!(greet.name().unwrap().lexeme, "greet");
+    let params: Vec<_> = greet.params().iter().map(|x| x.lexeme.as_str()).collect();
+    assert_eq!(params, ["name", "times"]);
+    let body: Vec<_> = greet.body().unwrap().statements().collect();
+    assert_eq!(body.len(), 3);
+    let StatementNode::Var(counter) = body[0] else {
+        panic!("expected a variable");
+    };
+    assert_eq!(counter.name().unwrap().lexeme, "i");
+    assert_eq!(counter.syntax().text(), "offering i = 0   ? counter\n");
+    let StatementNode::Return(ret) = body[2] else {
+        panic!("expected a return");
+    };
+    assert_eq!(ret.value().unwrap().syntax().kind, SyntaxKind::Grouping);
+
+    let StatementNode::If(branch) = statements[1] else {
+        panic!("expected an if");
+    };
+    let Some(ExpressionNode::Binary(condition)) = branch.condition() else {
+        panic!("expected a comparison");
+    };
+    assert_eq!(condition.operator().unwrap().token_type, TokenType::Is);
+    let Some(ExpressionNode::Call(call)) = condition.left() else {
+        panic!("expected a call");
+    };
+    assert_eq!(call.args().len(), 2);
+    assert!(branch.else_branch().is_some());
+
+    let StatementNode::Expression(set) = statements[2] else {
+        panic!("expected an expression");
+    };
+    let Some(ExpressionNode::Assign(assign)) = set.expression() else {
+        panic!("expected an assignment");
+    };
+    let Some(ExpressionNode::Get(target)) = assign.target() else {
+        panic!("expected a property");
+    };
+    assert_eq!(target.name().unwrap().lexeme, "field");
+    let Some(ExpressionNode::Get(value)) = assign.value() else {
+        panic!("expected a property");
+    };
+    assert_eq!(value.syntax().text(), "call(1, 2)(3).other");
+    assert_eq!(value.syntax().span().unwrap().line, 19);
+}
@@ -0,0 +1,167 @@
+//! Checks how diagnostics are rendered, against the exact text expected.
+
+use cahlang_ast::{Diagnostic, Lexer, Parser, Renderer, Source, Span};
+
+const PLAIN: Renderer = Renderer::new(false);
+
+/// Renders the first syntax error of a script without colors.
+fn render_error(source: &str) -> String {
+    let errors = Parser::new(Lexer::new(source.to_owned()))
+        .parse()
+        .expect_err(source);
+    PLAIN.render(&errors[0], Some(&Source::new("test.cah", source)))
+}
+
+#[test]
+fn snippet_with_caret_and_label() {
+    assert_eq!(
+        render_error("offering b = 1\nb + 1 = 3\n"),
+        "\
+error: Invalid assignment target.
+ --> test.cah:2:7
+  |
+2 | b + 1 = 3
+  |       ^
+2 | b + 1 = 3
+  | ----- Cannot be assigned to
+  = note: Only variables and properties can be assigned to.
+"
+    );
+    // A missing closer points at the end of the input and back at the opener.
+    assert_eq!(
+        render_error("if x {\n  $< 1\n"),
+        "\
+error: Expected '}' after block.
+ --> test.cah:3:1
+  |
+3 | 
+  | ^
+1 | if x {
+  |      - To match this '{'
+"
+    );
+}
+
+#[test]
+fn columns_count_characters() {
+    assert_eq!(
+        render_error("$< \"héllo\" + 👋\n"),
+        "\
+error: Unexpected character '👋'.
+ --> test.cah:1:14
+  |
+1 | $< \"héllo\" + 👋
+  |              ^
+"
+    );
+    // The caret is as wide as the text it points at, in characters rather than bytes.
+    let source = Source::new("test.cah", "$< \"héllo\" + 1\n");
+    let string = Span::new(3, 11, 1, 4);
+    let diagnostic = Diagnostic::error(string, "Wrong string.");
+    assert_eq!(
+        PLAIN.render(&diagnostic, Some(&source)),
+        "\
+error: Wrong string.
+ --> test.cah:1:4
+  |
+1 | $< \"héllo\" + 1
+  |    ^^^^^^^
+"
+    );
+    // A span from other text can end inside a character or past the end; the underline stops
+    // at the last whole character of the line instead.
+    let source = Source::new("<stdin>", "f() ? é\n");
+    for end in [7, 100] {
+        let diagnostic = Diagnostic::error(Span::new(6, end, 1, 7), "Elsewhere.");
+        assert_eq!(
+            PLAIN.render(&diagnostic, Some(&source)),
+            "\
+error: Elsewhere.
+ --> <stdin>:1:7
+  |
+1 | f() ? é
+  |       ^
+"
+        );
+    }
+}
+
+#[test]
+fn labels_notes_and_help() {
+    let text = "offering count = 0\n".repeat(9) + "ritual f() {\n}\n$< f(count)\n";
+    let source = Source::new("main.cah", &text);
+    let line_start = |line: usize| {
+        text.split_inclusive('\n')
+            .take(line - 1)
+            .map(str::len)
+            .sum()
+    };
+    let call = line_start(12) + 3;
+    let ritual = line_start(10);
+    let diagnostic = Diagnostic::error(
+        Span::new(call, call + 8, 12, 4),
+        "Wrong number of arguments.",
+    )
+    .with_label(
+        Span::new(ritual, ritual + 6, 10, 1),
+        "Declared without parameters here",
+    )
+    .with_note("Expected 0 arguments, but got 1.")
+    .with_note("in 'main' called at line 1, column 1")
+    .with_help("Remove the argument.");
+    // The gutter is as wide as the largest line number shown.
+    assert_eq!(
+        PLAIN.render(&diagnostic, Some(&source)),
+        "\
+error: Wrong number of arguments.
+  --> main.cah:12:4
+   |
+12 | $< f(count)
+   |    ^^^^^^^^
+10 | ritual f() {
+   | ------ Declared without parameters here
+   = note: Expected 0 arguments, but got 1.
+   = note: in 'main' called at line 1, column 1
+   = help: Remove the argument.
+"
+    );
+    // Without the source, only the position is shown.
+    assert_eq!(
+        PLAIN.render(&diagnostic, None),
+        "\
+error: Wrong number of arguments.
+  --> <unknown>:12:4
+   = note: Expected 0 arguments, but got 1.
+   = note: in 'main' called at line 1, column 1
+   = help: Remove the argument.
+"
+    );
+}
+
+#[test]
+fn colors_are_optional() {
+    let source = Source::new("test.cah", "offering = 1\n");
+    let diagnostic = Diagnostic::error(Span::new(9, 10, 1, 10), "Expected variable name.")
+        .with_help("Name the variable.");
+    let plain = PLAIN.render(&diagnostic, Some(&source));
+    assert!(!plain.contains('\x1b'));
+    let colored = Renderer::new(true).render(&diagnostic, Some(&source));
+    assert_eq!(
+        colored,
+        "\
+\x1b[1;31merror\x1b[0m\x1b[1m: Expected variable name.\x1b[0m
+ \x1b[1;34m-->\x1b[0m test.cah:1:10
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m offering = 1
+  \x1b[1;34m|\x1b[0m          \x1b[1;31m^\x1b[0m
+  \x1b[1;34m=\x1b[0m help: Name the variable.
+"
+    );
+    // Apart from the escape codes, the text is the same.
+    let mut stripped = colored.clone();
+    while let Some(start) = stripped.find('\x1b') {
+        let end = start + stripped[start..].find('m').unwrap() + 1;
+        stripped.replace_range(start..end, "");
+    }
+    assert_eq!(stripped, plain);
+}
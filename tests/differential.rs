@@ -0,0 +1,627 @@
+//! Runs the same scripts on both backends and checks that they behave identically.
+
+mod common;
+
+use cahlang_ast::{stdlib, Backend, CancellationToken, Interpreter, Value};
+use common::{parse, Harness};
+
+/// Everything a script run can be observed through.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: String,
+    errors: Vec<String>,
+    fatal: Option<String>,
+}
+
+fn run(backend: Backend, source: &str, setup: impl Fn(&mut Interpreter)) -> Outcome {
+    let mut harness = Harness::new(
+        Interpreter::builder()
+            .module(stdlib::math())
+            .backend(backend),
+    );
+    setup(&mut harness.interpreter);
+    let fatal = harness
+        .interpret(source)
+        .err()
+        .map(|err| format!("{:?} at {}: {err}", err.get_kind(), err.get_span()));
+    let errors = harness.map_errors(|err| {
+        let frames = err.get_traceback().len();
+        format!("{} ({frames} frames): {err}", err.get_span())
+    });
+    Outcome {
+        output: harness.output(),
+        errors,
+        fatal,
+    }
+}
+
+fn assert_same_with(source: &str, setup: impl Fn(&mut Interpreter)) -> Outcome {
+    let tree = run(Backend::TreeWalker, source, &setup);
+    let bytecode = run(Backend::Bytecode, source, &setup);
+    assert_eq!(tree, bytecode, "backends disagree on:\n{source}");
+    tree
+}
+
+fn assert_same(source: &str) -> Outcome {
+    assert_same_with(source, |_| {})
+}
+
+#[test]
+fn arithmetic_and_comparison() {
+    let outcome = assert_same(
+        r#"
+$< 1 + 2 * 3
+$< (1 + 2) * 3
+$< 10 / 4 - 1
+$< -3 + 1
+$< 2 < 3
+$< 3 <= 3
+$< 2 > 3
+$< 3 >= 4
+$< 1 is 1
+$< "a" is "b"
+$< none is none
+$< not none
+"#,
+    );
+    assert_eq!(
+        outcome.output,
+        "7\n9\n1.5\n-2\ntrue\ntrue\nfalse\nfalse\ntrue\nfalse\ntrue\ntrue\n"
+    );
+}
+
+#[test]
+fn strings() {
+    assert_same(
+        r#"
+$< "con" + "cat"
+$< "number " + 1.5
+$< "bool " + true
+$< "none " + none
+"#,
+    );
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    let outcome = assert_same(
+        r#"
+ritual loud(x) {
+    $< "evaluated " + x
+    return x
+}
+$< loud(false) and loud(true)
+$< loud(true) and loud(2)
+$< loud(1) or loud(2)
+$< loud(none) or loud("fallback")
+"#,
+    );
+    assert!(outcome.output.starts_with("evaluated false\nfalse\n"));
+}
+
+#[test]
+fn scopes_and_shadowing() {
+    let outcome = assert_same(
+        r#"
+offering x = "global"
+{
+    $< x
+    offering x = "outer"
+    {
+        $< x
+        offering x = x + " inner"
+        $< x
+        x = "assigned"
+        $< x
+    }
+    $< x
+    offering x = "redeclared"
+    $< x
+}
+$< x
+offering i = 0
+while i < 3 {
+    $< x
+    offering x = i
+    $< x
+    i = i + 1
+}
+if i is 3 {
+    offering y = "then"
+    $< y
+}
+else {
+    offering y = "else"
+    $< y
+}
+"#,
+    );
+    assert_eq!(outcome.errors, Vec::<String>::new());
+}
+
+#[test]
+fn rituals_only_see_globals() {
+    assert_same(
+        r#"
+offering g = 1
+ritual outer(a) {
+    offering local = a * 2
+    ritual inner(b) {
+        return b + g
+    }
+    g = g + 1
+    return inner(local)
+}
+$< outer(5)
+$< g
+ritual reads_missing() {
+    return local
+}
+reads_missing()
+{
+    ritual scoped(n) {
+        if n < 1 {
+            return "done"
+        }
+        return scoped(n - 1)
+    }
+    $< scoped(0)
+    $< scoped(2)
+}
+"#,
+    );
+}
+
+#[test]
+fn returns_from_loops_and_blocks() {
+    let outcome = assert_same(
+        r#"
+ritual find(limit) {
+    offering i = 0
+    while true {
+        if i * i > limit {
+            return i
+        }
+        i = i + 1
+    }
+}
+$< find(50)
+ritual nothing() {
+    return
+}
+$< nothing()
+ritual implicit() {
+    offering x = 1
+}
+$< implicit()
+{
+    $< "before"
+    return 5
+}
+$< "after"
+"#,
+    );
+    assert_eq!(outcome.output, "8\nnone\nnone\nbefore\nafter\n");
+}
+
+#[test]
+fn recursion() {
+    assert_same(
+        r#"
+ritual fib(n) {
+    if n < 2 {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+$< fib(15)
+ritual deep(n) {
+    return 1 + deep(n + 1)
+}
+deep(0)
+$< "still running"
+"#,
+    );
+}
+
+#[test]
+fn deep_recursion_fits_in_the_default_limit() {
+    let outcome = assert_same(
+        r#"
+ritual count(n) {
+    if n < 1 {
+        return 0
+    }
+    return 1 + count(n - 1)
+}
+$< count(1500)
+"#,
+    );
+    assert_eq!(outcome.output, "1500\n");
+}
+
+#[test]
+fn stack_overflow_collapses_the_traceback() {
+    let source = r#"
+ritual deep(n) {
+    return 1 + deep(n + 1)
+}
+ritual ping(n) {
+    return 1 + pong(n)
+}
+ritual pong(n) {
+    return 1 + ping(n)
+}
+"#;
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(Interpreter::builder().recursion_limit(50).backend(backend));
+        harness.interpret(source).unwrap();
+        let interpreter = &mut harness.interpreter;
+
+        let deep = interpreter.get_global("deep").unwrap();
+        let err = interpreter
+            .call(&deep, vec![Value::Number(0.)])
+            .unwrap_err();
+        assert_eq!(err.get_kind(), cahlang_ast::ErrorKind::StackOverflow);
+        assert_eq!(
+            err.to_string(),
+            "Stack overflow, calls were nested deeper than 50."
+        );
+        assert_eq!(err.get_traceback().len(), 50);
+        // The recursive calls are shown once, after them comes the call from the host.
+        let notes = cahlang_ast::Diagnostic::from(err).notes;
+        assert_eq!(
+            notes,
+            [
+                "in 'deep' called at line 3, column 16 (49 times)",
+                "in 'deep' called at line 1, column 1",
+            ]
+        );
+
+        // Alternating frames don't collapse, so only the innermost ones are shown.
+        let ping = interpreter.get_global("ping").unwrap();
+        let err = interpreter
+            .call(&ping, vec![Value::Number(0.)])
+            .unwrap_err();
+        let notes = cahlang_ast::Diagnostic::from(err).notes;
+        assert_eq!(notes.len(), 11);
+        assert_eq!(notes[0], "in 'pong' called at line 6, column 16");
+        assert_eq!(notes[1], "in 'ping' called at line 9, column 16");
+        assert_eq!(notes[10], "... and 40 more calls");
+    }
+}
+
+#[test]
+fn runtime_errors_continue_with_the_next_statement() {
+    let outcome = assert_same(
+        r#"
+$< missing
+missing = 1
+$< 1 + "a"
+$< -"a"
+$< "a" - 1
+$< 1 < "a"
+offering x = 1
+x()
+ritual two(a, b) {
+    return a
+}
+two(1)
+x.field
+x.field = 2
+$< "done"
+"#,
+    );
+    assert_eq!(outcome.errors.len(), 10);
+    assert_eq!(outcome.output, "done\n");
+}
+
+#[test]
+fn set_target_is_checked_before_the_value() {
+    assert_same(
+        r#"
+ritual side_effect() {
+    $< "evaluated"
+    return 1
+}
+offering x = 2
+x.field = side_effect()
+"#,
+    );
+}
+
+#[test]
+fn natives_and_modules() {
+    assert_same_with(
+        r#"
+$< math.sqrt(16)
+$< math.max(2, 7)
+$< math.pi > 3
+$< math.missing
+$< add(2, 3)
+$< add("a", 3)
+ritual apply(f, x) {
+    return f(x, x)
+}
+$< apply(add, 4)
+"#,
+        |interpreter| interpreter.register_fn("add", |a: f64, b: f64| a + b),
+    );
+}
+
+#[test]
+fn string_natives_count_characters() {
+    let outcome = assert_same_with(
+        r#"
+offering text = "héllo, wörld 👋"
+$< string.len(text)
+$< string.char_at(text, 1)
+$< string.char_at(text, 13)
+$< string.char_at(text, 14)
+$< string.slice(text, 7, 12)
+$< string.slice(text, 10, 100)
+$< string.slice(text, 5, 2)
+$< string.find(text, "wörld")
+$< string.find(text, "xyz")
+$< string.upper(text)
+$< string.chars("añb")
+$< string.char_at(text, -1)
+"#,
+        |interpreter| interpreter.register_module(stdlib::string()),
+    );
+    assert_eq!(
+        outcome.output,
+        "14\né\n👋\nnone\nwörld\nld 👋\n\n7\nnone\nHÉLLO, WÖRLD 👋\n[a, ñ, b]\n"
+    );
+    assert_eq!(outcome.errors.len(), 1);
+}
+
+#[test]
+fn rituals_can_be_called_from_the_host() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(Interpreter::builder().backend(backend));
+        harness
+            .interpret("ritual double(x) {\n return x * 2\n}\n")
+            .unwrap();
+        let double = harness.interpreter.get_global("double").unwrap();
+        let result = harness
+            .interpreter
+            .call(&double, vec![Value::Number(21.0)])
+            .unwrap();
+        assert!(matches!(result, Value::Number(x) if x == 42.0));
+    }
+}
+
+#[test]
+fn budgets_abort_both_backends() {
+    use cahlang_ast::{ErrorKind, InterpreterBuilder};
+    use std::time::Duration;
+
+    type Limit = fn(InterpreterBuilder) -> InterpreterBuilder;
+
+    let limits: [(Limit, &str, ErrorKind); 4] = [
+        (
+            |x| x.instruction_budget(1000),
+            "while true {\n}\n",
+            ErrorKind::InstructionLimit,
+        ),
+        (
+            |x| x.time_limit(Duration::from_millis(20)),
+            "while true {\n}\n",
+            ErrorKind::TimeLimit,
+        ),
+        (
+            |x| x.allocation_limit(10_000),
+            "offering s = \"\"\nwhile true {\n    s = s + \"0123456789\"\n}\n",
+            ErrorKind::AllocationLimit,
+        ),
+        // Lists returned by natives count too.
+        (
+            |x| x.allocation_limit(10_000),
+            "while true {\n    string.chars(\"abcd\")\n}\n",
+            ErrorKind::AllocationLimit,
+        ),
+    ];
+    for (limit, source, kind) in limits {
+        for backend in [Backend::TreeWalker, Backend::Bytecode] {
+            let builder = Interpreter::builder()
+                .module(stdlib::string())
+                .backend(backend);
+            let mut harness = Harness::new(limit(builder));
+            let err = harness
+                .interpret(&format!("{source}$< \"unreachable\"\n"))
+                .unwrap_err();
+            assert_eq!(err.get_kind(), kind, "{backend:?}");
+            assert!(err.is_fatal());
+        }
+    }
+}
+
+#[test]
+fn budgets_apply_to_each_run() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(
+            Interpreter::builder()
+                .instruction_budget(500)
+                .allocation_limit(1000)
+                .backend(backend),
+        );
+        // Each run uses most of both budgets, which would run out if they added up.
+        let source = r#"
+offering i = 0
+offering s = ""
+while i < 20 {
+    s = s + "abc"
+    i = i + 1
+}
+$< i
+"#;
+        for _ in 0..3 {
+            harness.interpret(source).unwrap();
+        }
+        assert_eq!(harness.output(), "20\n20\n20\n");
+    }
+}
+
+#[test]
+fn output_goes_to_the_buffer() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(
+            Interpreter::builder()
+                .module(stdlib::io())
+                .input(std::io::Cursor::new("typed\n"))
+                .backend(backend),
+        );
+        let output = harness.output.clone();
+        let mut run = |source: &str| harness.interpret(source).unwrap();
+        run(
+            "$< \"héllo\"\n$< 1.5\n$< none\nio.print(\"no newline\")\nio.println(io.read_line())\n",
+        );
+        assert_eq!(
+            output.contents(),
+            "héllo\n1.5\nnone\nno newlinetyped\n",
+            "{backend:?}"
+        );
+        // Later runs add to what is there, until the host clears it.
+        run("$< 2\n");
+        assert!(output.contents().ends_with("typed\n2\n"));
+        output.clear();
+        run("$< 3\n");
+        assert_eq!(output.contents(), "3\n");
+    }
+}
+
+#[test]
+fn cancelling_stops_a_running_script() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(Interpreter::builder().backend(backend));
+        let interpreter = &mut harness.interpreter;
+        let token = interpreter.get_cancellation_token();
+        let canceller = std::thread::spawn({
+            let token = token.clone();
+            move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                token.cancel();
+            }
+        });
+        let err = interpreter
+            .interpret(parse(
+                "while true {
+}
+$< \"unreachable\"\n",
+            ))
+            .unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(err.get_kind(), cahlang_ast::ErrorKind::Cancelled);
+        assert!(token.is_cancelled());
+
+        // The token stays cancelled until the host resets it.
+        let err = interpreter
+            .interpret(parse("while true {\n}\n"))
+            .unwrap_err();
+        assert_eq!(err.get_kind(), cahlang_ast::ErrorKind::Cancelled);
+        token.reset();
+        interpreter.interpret(parse("$< 1\n")).unwrap();
+        assert_eq!(harness.output(), "1\n");
+    }
+}
+
+#[test]
+fn cancelling_before_the_run_stops_it() {
+    let outcome = assert_same_with("$< 1\nwhile true {\n}\n$< 2\n", |interpreter| {
+        interpreter.get_cancellation_token().cancel()
+    });
+    // The token is only looked at in loops and calls, so the first statement still runs.
+    assert_eq!(outcome.output, "1\n");
+    assert_eq!(
+        outcome.fatal.as_deref(),
+        Some("Cancelled at line 2, column 1: Execution was cancelled.")
+    );
+}
+
+#[test]
+fn interpreters_can_share_a_token() {
+    let token = CancellationToken::new();
+    let mut harnesses = [Backend::TreeWalker, Backend::Bytecode].map(|backend| {
+        Harness::new(
+            Interpreter::builder()
+                .backend(backend)
+                .cancellation_token(token.clone()),
+        )
+    });
+    token.cancel();
+    // Starting a run on one interpreter doesn't take the cancel away from the others.
+    for harness in &mut harnesses {
+        let err = harness.interpret("while true {\n}\n").unwrap_err();
+        assert_eq!(err.get_kind(), cahlang_ast::ErrorKind::Cancelled);
+    }
+    assert!(token.is_cancelled());
+    token.reset();
+    for harness in &mut harnesses {
+        harness.interpret("$< 1\n").unwrap();
+    }
+}
+
+#[test]
+fn tail_calls_run_in_constant_space() {
+    // Far deeper than the default recursion limit, which only applies to nested calls.
+    let outcome = assert_same(
+        r#"
+ritual count(n, acc) {
+    if n is 0 {
+        return acc
+    }
+    return count(n - 1, acc + 1)
+}
+ritual even(n) {
+    if n is 0 {
+        return true
+    }
+    return odd(n - 1)
+}
+ritual odd(n) {
+    if n is 0 {
+        return false
+    }
+    return even(n - 1)
+}
+ritual sum(n) {
+    if n is 0 {
+        return 0
+    }
+    return n + sum(n - 1)
+}
+$< count(20000, 0)
+$< even(20001)
+$< sum(5000)
+"#,
+    );
+    assert_eq!(outcome.output, "20000\nfalse\n");
+    assert_eq!(outcome.errors.len(), 1);
+    assert!(outcome.errors[0].contains("Stack overflow"));
+}
+
+#[test]
+fn tail_call_errors_keep_a_traceback() {
+    let outcome = assert_same(
+        r#"
+ritual fail(n) {
+    if n is 0 {
+        return missing
+    }
+    return fail(n - 1)
+}
+ritual start() {
+    return fail(3)
+}
+ritual wrong() {
+    return start(1)
+}
+start()
+wrong()
+"#,
+    );
+    assert_eq!(outcome.errors.len(), 2);
+    assert!(outcome.errors[0].contains("(2 frames)"));
+    assert!(outcome.errors[1].contains("(1 frames)"));
+}
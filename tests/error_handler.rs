@@ -0,0 +1,96 @@
+//! Checks that error handlers are given every error of a session, and can be reset between the
+//! lines of a REPL.
+
+use cahlang_ast::{
+    CollectingErrorHandler, ErrorHandler, Interpreter, Lexer, OutputBuffer, Parser, Repl,
+};
+use std::{cell::RefCell, rc::Rc};
+
+#[test]
+fn collects_errors_until_reset() {
+    let err_handler = Rc::new(RefCell::new(CollectingErrorHandler::new()));
+    let output = OutputBuffer::new();
+    let mut interpreter = Interpreter::builder()
+        .error_handler(err_handler.clone())
+        .output(output.clone())
+        .build();
+    let mut repl = Repl::new();
+
+    // The handler is reset for every line, then given the syntax errors or, if there are none,
+    // the runtime errors.
+    repl.run_line(&mut interpreter, "offering x = 1 +\n");
+    {
+        let err_handler = err_handler.borrow();
+        assert!(err_handler.had_error());
+        assert!(!err_handler.had_runtime_error());
+        let messages: Vec<_> = err_handler.errors().iter().map(|x| &x.msg).collect();
+        assert_eq!(messages, ["Expected an expression."]);
+    }
+
+    // Each runtime error is collected, and the statements after it still run. Lines are counted
+    // from the start of the session.
+    repl.run_line(&mut interpreter, "$< 1 - \"a\"\n$< 2\n$< none()\n");
+    {
+        let err_handler = err_handler.borrow();
+        assert!(!err_handler.had_error());
+        assert!(err_handler.had_runtime_error());
+        let errors: Vec<_> = err_handler
+            .runtime_errors()
+            .iter()
+            .map(|x| (x.to_string(), x.get_span().line))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "Minus binary operator can only be used on numbers.".to_owned(),
+                    2
+                ),
+                ("Expected callable object, but got none.".to_owned(), 4),
+            ]
+        );
+    }
+
+    repl.run_line(&mut interpreter, "offering x = 3\n");
+    repl.run_line(&mut interpreter, "$< x\n");
+    let err_handler = err_handler.borrow();
+    assert!(!err_handler.had_error());
+    assert!(!err_handler.had_runtime_error());
+    assert!(err_handler.errors().is_empty());
+    assert!(err_handler.runtime_errors().is_empty());
+    assert_eq!(output.contents(), "2\n3\n");
+}
+
+#[test]
+fn errors_point_into_earlier_lines() {
+    let err_handler = Rc::new(RefCell::new(CollectingErrorHandler::new()));
+    let mut interpreter = Interpreter::builder()
+        .error_handler(err_handler.clone())
+        .output(OutputBuffer::new())
+        .build();
+    let mut repl = Repl::new();
+    let first = "ritual f() { $< 1 + none; }\n";
+    repl.run_line(&mut interpreter, first);
+    repl.run_line(&mut interpreter, "f() ? xxxxxxxxxxxxé\n");
+    let err_handler = err_handler.borrow();
+    let span = err_handler.runtime_errors()[0].get_span();
+    assert_eq!((span.line, span.column), (1, 19));
+    assert_eq!(&first[span.start..span.end], "+");
+}
+
+#[test]
+fn fatal_errors_are_returned_to_the_host() {
+    let err_handler = Rc::new(RefCell::new(CollectingErrorHandler::new()));
+    let mut interpreter = Interpreter::builder()
+        .error_handler(err_handler.clone())
+        .output(OutputBuffer::new())
+        .instruction_budget(100)
+        .build();
+    let statements = Parser::new(Lexer::new("$< 1 + none\nwhile true {\n}\n".to_owned()))
+        .parse()
+        .unwrap();
+    let err = interpreter.interpret(statements).unwrap_err();
+    assert!(err.is_fatal());
+    // Only the ordinary error went to the handler.
+    assert_eq!(err_handler.borrow().runtime_errors().len(), 1);
+}
@@ -0,0 +1,135 @@
+//! Checks the tokens the lexer makes for tricky input.
+
+use cahlang_ast::{token::TokenType, Lexer, Parser, Span, Value};
+
+fn lex(source: &str) -> Vec<(TokenType, String, Span)> {
+    let mut lexer = Lexer::new(source.to_owned());
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.lex();
+        let token_type = token.token_type;
+        tokens.push((token_type, token.lexeme, token.span));
+        if token_type == TokenType::EOF {
+            return tokens;
+        }
+    }
+}
+
+fn types(source: &str) -> Vec<TokenType> {
+    lex(source).into_iter().map(|(x, _, _)| x).collect()
+}
+
+#[test]
+fn end_of_input() {
+    use TokenType::*;
+    assert_eq!(types(""), [EOF]);
+    assert_eq!(types("   \t\n\n"), [EOF]);
+    assert_eq!(types("? only a comment"), [EOF]);
+    assert_eq!(types("x   "), [Identifier, StatementEnd, EOF]);
+    assert_eq!(types("\"open"), [Error, StatementEnd, EOF]);
+}
+
+#[test]
+fn multibyte_text() {
+    let source = "$< \"héllo 👋\" + x ? ünïcode comment\nx";
+    let tokens = lex(source);
+    let (token_type, lexeme, span) = &tokens[1];
+    assert_eq!(*token_type, TokenType::String);
+    assert_eq!(lexeme, "\"héllo 👋\"");
+    assert_eq!(&source[span.start..span.end], lexeme);
+    // Columns count characters, not bytes.
+    let (_, lexeme, span) = &tokens[3];
+    assert_eq!(lexeme, "x");
+    assert_eq!((span.line, span.column), (1, 16));
+    let (_, lexeme, span) = &tokens[5];
+    assert_eq!(lexeme, "x");
+    assert_eq!((span.line, span.column), (2, 1));
+
+    let token = Lexer::new("\"ü\"".to_owned()).lex();
+    assert!(matches!(token.literal, Value::String(x) if x == "ü"));
+}
+
+#[test]
+fn lexing_can_start_after_earlier_source() {
+    let source = "ritual f() {\n}\n$< \"é\" + x\n";
+    let start = source.find("$<").unwrap();
+    let mut lexer = Lexer::new(source.to_owned()).starting_at(start);
+    let token = lexer.lex();
+    assert_eq!(token.token_type, TokenType::DollarLess);
+    assert_eq!(token.span, Span::new(start, start + 2, 3, 1));
+    lexer.lex();
+    lexer.lex();
+    let token = lexer.lex();
+    assert_eq!(token.lexeme, "x");
+    assert_eq!((token.span.line, token.span.column), (3, 10));
+    assert_eq!(&source[token.span.start..token.span.end], "x");
+}
+
+#[test]
+fn unicode_identifiers() {
+    use TokenType::*;
+    let tokens = lex("offering größe = 1\n$< _名前 + größe");
+    let identifiers: Vec<_> = tokens
+        .iter()
+        .filter(|(x, _, _)| *x == Identifier)
+        .map(|(_, x, _)| x.as_str())
+        .collect();
+    assert_eq!(identifiers, ["größe", "_名前", "größe"]);
+    assert_eq!(
+        types("$< 👋"),
+        [DollarLess, Error, StatementEnd, EOF],
+        "emoji are not identifiers"
+    );
+}
+
+#[test]
+fn errors_are_tokens() {
+    let mut lexer = Lexer::new("x @ y € z $ \"open".to_owned());
+    let mut errors = vec![];
+    loop {
+        let token = lexer.lex();
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+        if let Some(msg) = token.error_message() {
+            errors.push((token.lexeme.clone(), msg.to_owned(), token.span.column));
+        }
+    }
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|(lexeme, msg, column)| (lexeme.as_str(), msg.as_str(), *column))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            ("@", "Unexpected character '@'.", 3),
+            ("€", "Unexpected character '€'.", 7),
+            ("$", "Expected '<' or '>' after '$'.", 11),
+            (
+                "\"open",
+                "Unterminated string, expected a closing '\"'.",
+                13
+            ),
+        ]
+    );
+}
+
+#[test]
+fn parser_reports_every_lexical_error() {
+    let source = "offering x = 1 @ 2\n$< x\n$< (x €) + \"open";
+    let errors = Parser::new(Lexer::new(source.to_owned()))
+        .parse()
+        .expect_err("script should fail to parse");
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|x| (x.msg.as_str(), x.span.line, x.span.column))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            ("Unexpected character '@'.", 1, 16),
+            ("Unexpected character '€'.", 3, 7),
+            ("Unterminated string, expected a closing '\"'.", 3, 12),
+        ]
+    );
+}
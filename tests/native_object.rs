@@ -0,0 +1,122 @@
+//! Checks native objects: method and property dispatch from scripts on both backends, and getting
+//! the Rust values back out.
+
+mod common;
+
+use cahlang_ast::{Backend, FromValue, Interpreter, NativeObject, NativeType, Value};
+use common::Harness;
+use std::rc::Rc;
+
+struct Counter {
+    count: i64,
+}
+
+struct Point;
+
+fn counter_type() -> NativeType {
+    NativeType::new("counter")
+        .with_method("add", 1, |interpreter, this, args| {
+            let amount = i64::from_value(args[0].clone())
+                .map_err(|_| interpreter.call_error("Can only add integers."))?;
+            let mut counter = this
+                .downcast_mut::<Counter>()
+                .ok_or_else(|| interpreter.call_error("Expected a counter."))?;
+            counter.count += amount;
+            Ok(Value::Number(counter.count as f64))
+        })
+        .with_method("take", 1, |interpreter, this, args| {
+            let other = NativeObject::from_value(args[0].clone())
+                .map_err(|err| interpreter.call_error(format!("Can't take from {}.", err.found)))?;
+            let mut other = other
+                .downcast_mut::<Counter>()
+                .ok_or_else(|| interpreter.call_error("Can only take from a counter."))?;
+            // Taking from itself leaves it borrowed above already.
+            let mut counter = this
+                .downcast_mut::<Counter>()
+                .ok_or_else(|| interpreter.call_error("Can't take from itself."))?;
+            counter.count += std::mem::take(&mut other.count);
+            Ok(Value::None)
+        })
+        .with_mut_property(
+            "count",
+            |_, this| {
+                Ok(Value::Number(
+                    this.downcast_ref::<Counter>().unwrap().count as f64,
+                ))
+            },
+            |interpreter, this, value| {
+                let count = i64::from_value(value)
+                    .map_err(|_| interpreter.call_error("A count must be an integer."))?;
+                this.downcast_mut::<Counter>().unwrap().count = count;
+                Ok(())
+            },
+        )
+}
+
+const SCRIPT: &str = r#"
+$< a.add(2)
+$< a.add(3)
+$< a.count
+a.count = 10
+offering add = a.add
+$< add(1)
+a.take(b)
+$< "" + a.count + " " + b.count
+$< a.add("x")
+$< a.take(p)
+$< a.take(1)
+$< a.take(a)
+$< p.add(1)
+$< a.missing
+$< fake.add(1)
+a.count = 0.5
+$< a.count
+"#;
+
+#[test]
+fn methods_and_properties_dispatch_on_both_backends() {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut harness = Harness::new(Interpreter::builder().backend(backend));
+        let interpreter = &mut harness.interpreter;
+        interpreter.register_type::<Counter>(counter_type());
+        interpreter.register_type::<Point>(NativeType::new("point"));
+
+        let a = interpreter.new_object(Counter { count: 0 }).unwrap();
+        let b = interpreter.new_object(Counter { count: 4 }).unwrap();
+        let p = interpreter.new_object(Point).unwrap();
+        // A counter that doesn't hold one, which its methods have to notice.
+        let fake = NativeObject::new(Rc::new(counter_type()), Point);
+        for (name, object) in [("a", &a), ("b", &b), ("p", &p), ("fake", &fake)] {
+            interpreter.define_global(name, Value::Native(object.clone()));
+        }
+        assert!(interpreter.new_object(1u8).is_none());
+
+        harness.interpret(SCRIPT).unwrap();
+        assert_eq!(harness.output(), "2\n5\n5\n11\n15 0\n15\n", "{backend:?}");
+        assert_eq!(
+            harness.errors(),
+            [
+                "Can only add integers.",
+                "Can only take from a counter.",
+                "Can't take from number.",
+                "Can't take from itself.",
+                "Undefined property 'add' on point",
+                "Undefined property 'missing' on counter",
+                "Expected a counter.",
+                "A count must be an integer.",
+            ],
+            "{backend:?}"
+        );
+
+        // The host still owns the values the script changed.
+        assert!(a.is::<Counter>() && !a.is::<Point>());
+        assert_eq!(a.downcast_ref::<Counter>().unwrap().count, 15);
+        assert_eq!(b.downcast_ref::<Counter>().unwrap().count, 0);
+        assert!(a.downcast_ref::<Point>().is_none());
+        assert!(p.downcast_mut::<Counter>().is_none());
+        let value = harness.interpreter.get_global("a").unwrap();
+        let object = NativeObject::from_value(value).unwrap();
+        assert!(object.ptr_eq(&a));
+        assert_eq!(object.get_type_name(), "counter");
+    }
+}
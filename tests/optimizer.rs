@@ -0,0 +1,203 @@
+//! Checks that the optimizer simplifies scripts without changing what they do.
+
+mod common;
+
+use cahlang_ast::{
+    expression::Expression, statement::Statement, Backend, Interpreter, InterpreterConfig,
+    Optimizer, Value,
+};
+use common::{parse, Harness};
+
+fn optimize(source: &str) -> Vec<Statement> {
+    Optimizer::new(&InterpreterConfig::default()).optimize(parse(source))
+}
+
+/// Runs a script, returning its output and the runtime errors it reported.
+fn run(backend: Backend, optimize: bool, source: &str) -> (String, Vec<String>) {
+    let mut harness = Harness::new(Interpreter::builder().backend(backend).optimize(optimize));
+    harness.interpret(source).unwrap();
+    (harness.output(), harness.located_errors())
+}
+
+fn assert_unchanged(source: &str) -> (String, Vec<String>) {
+    let expected = run(Backend::TreeWalker, false, source);
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        assert_eq!(
+            run(backend, true, source),
+            expected,
+            "optimizing changed the behavior of:\n{source}"
+        );
+    }
+    expected
+}
+
+fn literal(statement: &Statement) -> &Value {
+    let expr = match statement {
+        Statement::Print(x) => &x.expr,
+        Statement::Var(x) => x.initializer.as_ref().unwrap(),
+        x => panic!("expected a print or a variable, got {x:?}"),
+    };
+    match expr {
+        Expression::Literal(x) => &x.value,
+        x => panic!("expected a literal, got {x:?}"),
+    }
+}
+
+#[test]
+fn folds_constants() {
+    let statements = optimize(
+        r#"
+offering day = 24 * 60 * 60
+$< (1 + 2) * -3
+$< 2 * 3 > 5
+$< "a" + "b" + 1
+$< not none is true
+$< 1 is 1 and "yes"
+$< none or 4 / 2
+"#,
+    );
+    let values: Vec<_> = statements.iter().map(literal).collect();
+    let expected = [
+        Value::Number(86400.0),
+        Value::Number(-9.0),
+        Value::Boolean(true),
+        Value::String("ab1".to_owned()),
+        Value::Boolean(true),
+        Value::String("yes".to_owned()),
+        Value::Number(2.0),
+    ];
+    assert_eq!(values.len(), expected.len());
+    for (value, expected) in values.into_iter().zip(expected) {
+        assert_eq!(value.to_string(), expected.to_string());
+    }
+}
+
+#[test]
+fn leaves_failing_and_variable_expressions() {
+    let statements = optimize(
+        r#"
+offering x = 1
+$< x + 2 * 3
+$< "a" - 1
+"#,
+    );
+    for statement in &statements[1..] {
+        let Statement::Print(x) = statement else {
+            panic!("expected a print, got {statement:?}");
+        };
+        assert!(matches!(x.expr, Expression::Binary(_)));
+    }
+}
+
+#[test]
+fn strict_concat_is_respected() {
+    let config = InterpreterConfig {
+        strict_concat: true,
+        ..Default::default()
+    };
+    let statements = Optimizer::new(&config).optimize(parse("$< \"a\" + 1\n"));
+    let Statement::Print(x) = &statements[0] else {
+        panic!("expected a print");
+    };
+    assert!(matches!(x.expr, Expression::Binary(_)));
+}
+
+#[test]
+fn simplifies_constant_branches() {
+    let statements = optimize(
+        r#"
+if true {
+    $< 1
+}
+else {
+    $< 2
+}
+if 1 > 2 {
+    $< 3
+}
+while false {
+    $< 4
+}
+if 1 > 2 {
+    $< 5
+}
+else {
+    $< 6
+}
+"#,
+    );
+    assert_eq!(statements.len(), 2);
+    assert!(statements
+        .iter()
+        .all(|x| matches!(x, Statement::Block(x) if x.statements.len() == 1)));
+}
+
+#[test]
+fn removes_unreachable_statements() {
+    let statements = optimize(
+        r#"
+ritual f(x) {
+    if x {
+        return 1
+    }
+    else {
+        return 2
+    }
+    $< "unreachable"
+}
+ritual g() {
+    {
+        return 3
+    }
+    $< "unreachable"
+}
+"#,
+    );
+    let bodies: Vec<_> = statements
+        .iter()
+        .map(|x| match x {
+            Statement::Function(x) => &x.body,
+            x => panic!("expected a ritual, got {x:?}"),
+        })
+        .collect();
+    assert_eq!(bodies[0].len(), 1);
+    assert_eq!(bodies[1].len(), 1);
+    let Statement::Block(block) = &bodies[1][0] else {
+        panic!("expected a block");
+    };
+    assert_eq!(block.statements.len(), 1);
+}
+
+#[test]
+fn behavior_is_unchanged() {
+    let (output, errors) = assert_unchanged(
+        r#"
+ritual f(x) {
+    offering y = 2 * 3
+    if true {
+        return x + y
+    }
+    $< "unreachable"
+}
+$< f(1)
+$< 1 + 2 * 3
+$< "total: " + 10 / 4
+$< false and missing
+$< true or missing
+$< none or "fallback"
+$< "a" - 1
+$< -"a"
+if 1 {
+    offering scoped = 1
+}
+$< scoped
+offering i = 0
+while i < 3 {
+    i = i + 1
+}
+$< i
+"#,
+    );
+    assert_eq!(output, "7\n7\ntotal: 2.5\nfalse\ntrue\nfallback\n3\n");
+    assert_eq!(errors.len(), 3);
+}
@@ -0,0 +1,382 @@
+//! Checks where statements and blocks end: at newlines, at ';', in strict mode and at `end`. Also checks
+//! that the parser recovers from errors and reports each of them where it is.
+
+use cahlang_ast::{statement::Statement, Diagnostic, Lexer, Parser};
+
+fn parse_with(source: &str, strict: bool) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    Parser::new(Lexer::new(source.to_owned()).strict_terminators(strict)).parse()
+}
+
+fn parse(source: &str) -> Vec<Statement> {
+    parse_with(source, false).unwrap_or_else(|x| panic!("{source:?} should parse: {x:?}"))
+}
+
+fn parse_strict(source: &str) -> Vec<Statement> {
+    parse_with(source, true).unwrap_or_else(|x| panic!("{source:?} should parse: {x:?}"))
+}
+
+fn kinds(statements: &[Statement]) -> Vec<&'static str> {
+    statements
+        .iter()
+        .map(|x| match x {
+            Statement::Expression(_) => "expression",
+            Statement::Print(_) => "print",
+            Statement::Var(_) => "var",
+            Statement::Function(_) => "ritual",
+            Statement::Block(_) => "block",
+            Statement::If(_) => "if",
+            Statement::While(_) => "while",
+            Statement::Return(_) => "return",
+        })
+        .collect()
+}
+
+#[test]
+fn semicolons_end_statements() {
+    assert_eq!(kinds(&parse("$< 1; $< 2")), ["print", "print"]);
+    assert_eq!(
+        kinds(&parse("offering x = 1;; x = 2;\n\n$< x;")),
+        ["var", "expression", "print"]
+    );
+    // Newlines and semicolons can be mixed.
+    assert_eq!(
+        kinds(&parse("offering x = 1\n$< x; $< x\n")),
+        ["var", "print", "print"]
+    );
+    assert!(parse_with("$< (1; 2)", false).is_err());
+}
+
+#[test]
+fn trailing_operator_continues_the_line() {
+    for strict in [false, true] {
+        let source = "offering x = 1 +\n    2 *\n    3;\n$< x;";
+        let statements = parse_with(source, strict).unwrap();
+        assert_eq!(kinds(&statements), ["var", "print"]);
+        let Statement::Var(x) = &statements[0] else {
+            panic!("expected a variable");
+        };
+        assert_eq!(x.span.line, 1);
+    }
+    assert_eq!(
+        kinds(&parse("f(1,\n  2)\n$< 1 and\n  2")),
+        ["expression", "print"]
+    );
+}
+
+#[test]
+fn body_can_start_on_the_next_line() {
+    let sources = [
+        (
+            "ritual f()\n{\n    return 1\n}\n$< f()\n",
+            "ritual f()\n{\n    return 1;\n}\n$< f();\n",
+        ),
+        (
+            "ritual f(a,\n    b)\n{\n    return a\n}\n",
+            "ritual f(a,\n    b)\n{\n    return a;\n}\n",
+        ),
+        (
+            "while x\n{\n    x = x - 1\n}\n",
+            "while x\n{\n    x = x - 1;\n}\n",
+        ),
+    ];
+    for (source, strict) in sources {
+        assert_eq!(kinds(&parse(source)), kinds(&parse_strict(strict)));
+    }
+    // A semicolon really does end the statement.
+    assert!(parse_with("ritual f();\n{\n}\n", false).is_err());
+    assert!(parse_with("ritual f()\n", false).is_err());
+}
+
+#[test]
+fn else_can_follow_on_the_next_line() {
+    let sources = [
+        (
+            "if x {\n    $< 1\n}\nelse {\n    $< 2\n}\n$< 3\n",
+            "if x {\n    $< 1;\n}\nelse {\n    $< 2;\n}\n$< 3;\n",
+        ),
+        (
+            "if x {\n    $< 1\n} else {\n    $< 2\n}\n$< 3\n",
+            "if x {\n    $< 1;\n} else {\n    $< 2;\n}\n$< 3;\n",
+        ),
+        (
+            "if x\n{\n    $< 1\n}\nelse\n{\n    $< 2\n}\n$< 3\n",
+            "if x\n{\n    $< 1;\n}\nelse\n{\n    $< 2;\n}\n$< 3;\n",
+        ),
+        (
+            "if x { $< 1; } else { $< 2; }\n$< 3\n",
+            "if x { $< 1; } else { $< 2; } $< 3;",
+        ),
+    ];
+    for (source, strict) in sources {
+        for statements in [parse(source), parse_strict(strict)] {
+            assert_eq!(kinds(&statements), ["if", "print"], "{source}");
+            let Statement::If(x) = &statements[0] else {
+                unreachable!();
+            };
+            assert!(x.else_branch.is_some(), "{source}");
+        }
+    }
+    // A block ended by a ';' can't be continued with an else.
+    assert!(parse_with("if x {\n};\nelse {\n}\n", false).is_err());
+}
+
+#[test]
+fn strict_mode_requires_semicolons() {
+    assert_eq!(
+        kinds(&parse_strict(
+            "offering x = 1;\nritual f(a) {\n    $< a;\n    return a\n}\nwhile x < 3 {\n    x = x + 1;\n}\n"
+        )),
+        ["var", "ritual", "while"]
+    );
+    for source in [
+        "$< 1\n$< 2;",
+        "$< 1;\n$< 2",
+        "offering x = 1",
+        "{\n    f()\n}",
+    ] {
+        let errors = parse_with(source, true).expect_err(source);
+        assert_eq!(errors.len(), 1, "{source}");
+        assert!(parse_with(source, false).is_ok(), "{source}");
+    }
+    // Without semicolons, a whole script is one statement that runs on over its newlines.
+    let errors = parse_with("$< 1\n$< 2\n", true).unwrap_err();
+    assert_eq!((errors[0].span.line, errors[0].span.column), (2, 1));
+}
+
+/// Formats statements without their spans, which differ between equivalent sources.
+fn shape(statements: &[Statement]) -> String {
+    let mut text = format!("{statements:?}");
+    while let Some(start) = text.find("Span {") {
+        let end = start + text[start..].find('}').unwrap() + 1;
+        text.replace_range(start..end, "Span");
+    }
+    text
+}
+
+#[test]
+fn end_closes_blocks_like_braces() {
+    let braces = r#"
+ritual sign(x) {
+    if x < 0 {
+        return -1
+    }
+    else {
+        if x is 0 {
+            return 0
+        }
+    }
+    return 1
+}
+offering i = 0
+while i < 3 {
+    $< sign(i - 1)
+    i = i + 1
+}
+"#;
+    let ends = r#"
+ritual sign(x)
+    if x < 0
+        return -1
+    else
+        if x is 0
+            return 0
+        end
+    end
+    return 1
+end
+offering i = 0
+while i < 3
+    $< sign(i - 1)
+    i = i + 1
+end
+"#;
+    let mixed = r#"
+ritual sign(x)
+    if x < 0 {
+        return -1
+    } else
+        if x is 0 { return 0 }
+    end
+    return 1
+end
+offering i = 0
+while i < 3 { $< sign(i - 1); i = i + 1; }
+"#;
+    let expected = shape(&parse(braces));
+    assert_eq!(shape(&parse(ends)), expected);
+    assert_eq!(shape(&parse(mixed)), expected);
+    assert_eq!(shape(&parse_strict(&ends.replace("\n", ";\n"))), expected);
+    assert_eq!(
+        shape(&parse("if x $< 1; end")),
+        shape(&parse("if x { $< 1; }"))
+    );
+    assert_eq!(
+        shape(&parse("if x\nelse\nend")),
+        shape(&parse("if x {\n} else {\n}"))
+    );
+}
+
+#[test]
+fn end_blocks_report_what_is_missing() {
+    let errors = parse_with("while x\n    x = x - 1\n", false).unwrap_err();
+    assert_eq!(errors[0].msg, "Expected 'end' after block.");
+    assert_eq!(errors[0].labels[0].span.line, 1);
+    // `end` closes the whole `if`, so an `else` can't come after it.
+    assert!(parse_with("if x\n    $< 1\nend\nelse\n    $< 2\nend\n", false).is_err());
+    assert!(parse_with("if x\n    $< 1\nend else {\n}\n", false).is_err());
+    // Returning is still only allowed at the end of a block.
+    assert!(parse_with("ritual f()\n    return 1\n    $< 2\nend\n", false).is_err());
+    assert!(parse_with(
+        "ritual f()\n    if x\n        return 1\n    else\n        return 2\n    end\nend\n",
+        false
+    )
+    .is_ok());
+}
+
+/// Parses a broken script, giving each error as its message with the line and column it is at.
+fn errors(source: &str) -> Vec<(String, usize, usize)> {
+    parse_with(source, false)
+        .expect_err(source)
+        .into_iter()
+        .map(|x| (x.msg, x.span.line, x.span.column))
+        .collect()
+}
+
+#[test]
+fn closers_of_other_blocks_are_skipped() {
+    let expected = [
+        ("{\n  end\n}\n$< 1", vec![("Expected an expression.", 2, 3)]),
+        ("{ else }", vec![("Expected an expression.", 1, 3)]),
+        ("if true\n  }\nend", vec![("Expected an expression.", 2, 3)]),
+        (
+            "ritual f(n)\n  if n < 1 { return 0 }\n  return f(n - 1)\n}\n$< f(10)",
+            vec![
+                (
+                    "Return must be the last statement in a block. (preceeding '}' or 'end')",
+                    3,
+                    3,
+                ),
+                ("Unexpected '}'.", 4, 1),
+                ("Expected 'end' after block.", 5, 9),
+            ],
+        ),
+        (
+            "while x\n  $< 1 }\n  $< 2\nend\n$< 3",
+            vec![("Expected statement end after expression.", 2, 8)],
+        ),
+    ];
+    for (source, expected) in expected {
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(msg, line, column)| (msg.to_owned(), line, column))
+            .collect();
+        assert_eq!(errors(source), expected, "{source:?}");
+    }
+}
+
+#[test]
+fn one_line_end_blocks_need_statement_ends() {
+    assert_eq!(
+        shape(&parse("ritual f(x) $< x; end")),
+        shape(&parse("ritual f(x) {\n    $< x\n}"))
+    );
+    assert_eq!(
+        errors("ritual f(x) $< x end"),
+        [("Expected statement end after expression.".to_owned(), 1, 18)]
+    );
+    // A return needs none, since it has to be the last statement anyway.
+    assert_eq!(kinds(&parse("ritual f(x) return x end")), ["ritual"]);
+}
+
+fn assert_errors(source: &str, expected: &[(&str, usize, usize)]) {
+    let found = errors(source);
+    let found: Vec<_> = found
+        .iter()
+        .map(|(msg, line, column)| (msg.as_str(), *line, *column))
+        .collect();
+    assert_eq!(found, expected, "{source:?}");
+}
+
+#[test]
+fn recovery_resumes_at_the_next_statement() {
+    assert_errors(
+        "offering = 1\n$< 2\nx = = 3\n$< 4 4\n$< 5\n",
+        &[
+            ("Expected variable name.", 1, 10),
+            ("Expected an expression.", 3, 5),
+            ("Expected statement end after expression.", 4, 6),
+        ],
+    );
+    // Lexical errors in the skipped part of a statement are reported too.
+    assert_errors(
+        "offering = 1 @ 2\n$< 3",
+        &[
+            ("Expected variable name.", 1, 10),
+            ("Unexpected character '@'.", 1, 14),
+        ],
+    );
+    // One error per broken statement, not one per token.
+    assert_errors(
+        "$< 1 2 3 4\n$< 5",
+        &[("Expected statement end after expression.", 1, 6)],
+    );
+}
+
+#[test]
+fn recovery_continues_inside_nested_blocks() {
+    let source = r#"ritual f(a) {
+  $< a + * 2
+  while a {
+    offering = 2
+    a = a - 1
+  }
+  $< @
+}
+if f(1)
+  $< ]
+else
+  offering 3
+end
+$< f(1)
+"#;
+    assert_errors(
+        source,
+        &[
+            ("Expected an expression.", 2, 10),
+            ("Expected variable name.", 4, 14),
+            ("Unexpected character '@'.", 7, 6),
+            ("Expected an expression.", 10, 6),
+            ("Expected variable name.", 12, 12),
+        ],
+    );
+}
+
+#[test]
+fn nesting_is_limited() {
+    let parens = |depth: usize| format!("$< {}1{}\n", "(".repeat(depth), ")".repeat(depth));
+    let blocks = |depth: usize| format!("{}$< 1\n{}", "{\n".repeat(depth), "}\n".repeat(depth));
+    for source in [parens(60), blocks(60)] {
+        parse(&source);
+    }
+    assert_errors(&parens(500), &[("Code is nested too deeply.", 1, 131)]);
+    let errors = parse_with(&blocks(500), false).unwrap_err();
+    assert_eq!(errors[0].msg, "Code is nested too deeply.");
+    assert_eq!((errors[0].span.line, errors[0].span.column), (129, 1));
+}
+
+#[test]
+fn long_chains_count_towards_the_nesting_limit() {
+    let sum = |terms: usize| format!("$< 1{}\n", "+1".repeat(terms - 1));
+    let assignments = |depth: usize| format!("a = {}1\n", "a = ".repeat(depth - 1));
+    let gets = |depth: usize| format!("$< a{}\n", ".b".repeat(depth));
+    for source in [sum(100), assignments(100), gets(100)] {
+        parse(&source);
+    }
+    for source in [sum(5000), assignments(3000), gets(3000)] {
+        let errors = parse_with(&source, false).unwrap_err();
+        assert_eq!(
+            errors[0].msg, "Code is nested too deeply.",
+            "for {source:.20}…"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Checks that compiled programs survive being saved and loaded, and that bad files are rejected.
+
+mod common;
+
+use cahlang_ast::{
+    bytecode::{self, Constant, Instruction, Program, Prototype},
+    compiler::Compiler,
+    serialize::{read_program, write_program, LoadError, FORMAT_VERSION},
+    Backend, Interpreter, Span, Value,
+};
+use common::{parse, Harness};
+use std::rc::Rc;
+
+const SOURCE: &str = r#"
+ritual fib(n) {
+    if n < 2 {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+offering i = 0
+while i < 10 {
+    $< fib(i)
+    i = i + 1
+}
+$< "done" + "!"
+$< not none and true
+$< missing
+"#;
+
+fn compile(source: &str) -> Vec<u8> {
+    let program = Compiler::compile_program(&parse(source)).expect("test script should compile");
+    let mut bytes = vec![];
+    write_program(&program, &mut bytes).unwrap();
+    bytes
+}
+
+/// Runs a program, returning its output and the runtime errors it reported.
+fn run(program: &Program) -> (String, Vec<String>) {
+    let mut harness = Harness::new(Interpreter::builder().backend(Backend::Bytecode));
+    harness.interpreter.run_program(program).unwrap();
+    (harness.output(), harness.located_errors())
+}
+
+fn write(program: &Program) -> Vec<u8> {
+    let mut bytes = vec![];
+    write_program(program, &mut bytes).unwrap();
+    bytes
+}
+
+fn load_error(bytes: &[u8]) -> LoadError {
+    read_program(&mut &bytes[..]).expect_err("file should be rejected")
+}
+
+#[test]
+fn round_trip_behaves_like_source() {
+    let bytes = compile(SOURCE);
+    let loaded = read_program(&mut &bytes[..]).unwrap();
+    let original = Compiler::compile_program(&parse(SOURCE)).unwrap();
+
+    let (output, errors) = run(&loaded);
+    assert_eq!(output, "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\ndone!\ntrue\n");
+    assert_eq!(errors.len(), 1);
+    assert_eq!((output, errors), run(&original));
+    // Writing the loaded program again gives back the same file.
+    assert_eq!(write(&loaded), bytes);
+}
+
+#[test]
+fn rejects_other_files() {
+    assert!(matches!(load_error(b""), LoadError::NotCompiled));
+    assert!(matches!(
+        load_error(b"offering x = 1\n"),
+        LoadError::NotCompiled
+    ));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut bytes = compile(SOURCE);
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    let err = load_error(&bytes);
+    assert!(matches!(err, LoadError::UnsupportedVersion(x) if x == FORMAT_VERSION + 1));
+    assert!(err.to_string().contains("Recompile"));
+}
+
+#[test]
+fn rejects_damaged_files() {
+    let bytes = compile(SOURCE);
+    for len in [5, 10, 14, bytes.len() / 2, bytes.len() - 1] {
+        let err = load_error(&bytes[..len]);
+        assert!(matches!(err, LoadError::Corrupt(_)), "{len}: {err}");
+    }
+    for index in [14, bytes.len() / 2, bytes.len() - 1] {
+        let mut damaged = bytes.clone();
+        damaged[index] ^= 0x10;
+        assert!(matches!(load_error(&damaged), LoadError::Corrupt(_)));
+    }
+    let mut extended = bytes.clone();
+    extended.push(0);
+    assert!(matches!(load_error(&extended), LoadError::Corrupt(_)));
+}
+
+fn program(code: Vec<Instruction>, constants: Vec<Constant>) -> Program {
+    let proto = Prototype {
+        name: "<script>".to_owned(),
+        spans: vec![Span::new(0, 0, 1, 1); code.len()],
+        code,
+        constants,
+        ..Default::default()
+    };
+    Program {
+        statements: vec![Rc::new(proto)],
+    }
+}
+
+#[test]
+fn rejects_invalid_code() {
+    use Instruction::*;
+    let none = || bytecode::Constant::Value(Value::None);
+    let cases = [
+        // Reads a local slot that doesn't exist.
+        program(vec![GetLocal(0), Return], vec![]),
+        // Pushes a constant that doesn't exist.
+        program(vec![Constant(1), Return], vec![none()]),
+        // Pops from an empty stack.
+        program(vec![Pop, Constant(0), Return], vec![none()]),
+        // Jumps outside the code.
+        program(vec![Jump(7)], vec![]),
+        // Runs off the end of the code.
+        program(vec![Constant(0), Pop], vec![none()]),
+        // Reaches the same instruction with different stack heights.
+        program(vec![Constant(0), Loop(0)], vec![none()]),
+    ];
+    for program in cases {
+        let err = load_error(&write(&program));
+        assert!(matches!(err, LoadError::Corrupt(_)), "{err}");
+    }
+}